@@ -0,0 +1,265 @@
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use log::info;
+
+use psitool::config::Config;
+use psitool::journal::Journal;
+use psitool::logger;
+use psitool::persist;
+use psitool::rvuid::Rvuid;
+use psitool::target::{CompletedTarget, parse_hit, parse_score};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value = "~/.psitool_completed_targets.yaml",
+        help = "the yaml config with a list of completed targets (used to cache what you RV'd already)"
+    )]
+    completed: String,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the viewer profiles (only read with --viewer)"
+    )]
+    config: String,
+
+    #[arg(
+        short = 'u',
+        long,
+        conflicts_with = "completed",
+        help = "the viewer profile from the config whose completed targets to use"
+    )]
+    viewer: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List completed targets with their number, for use with the other commands
+    List {
+        #[arg(long, help = "only list entries that haven't been reopened")]
+        active: bool,
+    },
+    /// Print everything recorded for an entry
+    Show {
+        #[arg(help = "the entry number from `list`, or its RVUID (full or 40-bit prefix)")]
+        entry: String,
+    },
+    /// Change the recorded results of an entry
+    Edit {
+        #[arg(help = "the entry number from `list`, or its RVUID (full or 40-bit prefix)")]
+        entry: String,
+
+        #[arg(long, help = "whether it was a hit (y/yes/n/no, or blank to clear it)")]
+        hit: Option<String>,
+
+        #[arg(long, help = "the score out of 100 (or blank to clear it)")]
+        score: Option<String>,
+
+        #[arg(long, help = "notes about the session (or blank to clear them)")]
+        notes: Option<String>,
+
+        #[arg(long, help = "a file with the session transcript (- for stdin)")]
+        transcript: Option<String>,
+    },
+    /// Make the target eligible to be picked again, keeping the entry as history
+    Reopen {
+        #[arg(help = "the entry number from `list`, or its RVUID (full or 40-bit prefix)")]
+        entry: String,
+    },
+    /// Delete an entry entirely
+    Remove {
+        #[arg(help = "the entry number from `list`, or its RVUID (full or 40-bit prefix)")]
+        entry: String,
+    },
+    /// Fold edits and removals in a .jsonl journal into one event per session
+    Compact,
+    /// Add the entries of a YAML completed targets file to a .jsonl journal
+    Import {
+        #[arg(help = "the yaml file to import, e.g. ~/.psitool_completed_targets.yaml")]
+        yaml: String,
+    },
+}
+
+fn summary(idx: usize, ct: &CompletedTarget) -> String {
+    let hit = match ct.hit {
+        Some(true) => "hit",
+        Some(false) => "miss",
+        None => "-",
+    };
+    let score = ct.score.map(|s| s.to_string()).unwrap_or("-".to_string());
+    let when = ct
+        .completed_at
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or("-".to_string());
+    let reopened = if ct.reopened_at.is_some() {
+        " (reopened)"
+    } else {
+        ""
+    };
+    format!(
+        "{:>4}  {}  {:<16}  {:<4}  {:>3}  {}{}",
+        idx + 1,
+        ct.rvuid,
+        when,
+        hit,
+        score,
+        ct.path,
+        reopened
+    )
+}
+
+/// Resolve an entry number (1-based, as printed by `list`) or an RVUID to a single entry.
+fn resolve(completed_targets: &[CompletedTarget], entry: &str) -> anyhow::Result<usize> {
+    if let Ok(n) = entry.parse::<usize>() {
+        if n == 0 || n > completed_targets.len() {
+            anyhow::bail!(
+                "no entry {}, there are {} completed targets",
+                n,
+                completed_targets.len()
+            );
+        }
+        return Ok(n - 1);
+    }
+    let rvuid: Rvuid = entry.parse()?;
+    let matches: Vec<usize> = completed_targets
+        .iter()
+        .enumerate()
+        .filter(|(_, ct)| ct.rvuid == rvuid)
+        .map(|(i, _)| i)
+        .collect();
+    match matches.len() {
+        0 => anyhow::bail!("no completed target matches {}", rvuid),
+        1 => Ok(matches[0]),
+        _ => {
+            let list: Vec<String> = matches
+                .iter()
+                .map(|&i| summary(i, &completed_targets[i]))
+                .collect();
+            anyhow::bail!(
+                "{} matches more than one entry, pass its number instead:\n{}",
+                rvuid,
+                list.join("\n")
+            );
+        }
+    }
+}
+
+fn journal(path: &str) -> anyhow::Result<Journal> {
+    if !Journal::is_journal(path) {
+        anyhow::bail!(
+            "{} isn't a journal, pass one ending in .jsonl with --completed",
+            path
+        );
+    }
+    Ok(Journal::open(path))
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    if let Some(ref name) = args.viewer {
+        args.completed = Config::load(&args.config)?
+            .get_viewer(name)?
+            .completed
+            .clone();
+    }
+    match args.command {
+        Command::List { active } => {
+            let completed_targets = CompletedTarget::parse(&args.completed)?;
+            for (i, ct) in completed_targets.iter().enumerate() {
+                if !active || ct.reopened_at.is_none() {
+                    println!("{}", summary(i, ct));
+                }
+            }
+        }
+        Command::Show { entry } => {
+            let completed_targets = CompletedTarget::parse(&args.completed)?;
+            let idx = resolve(&completed_targets, &entry)?;
+            print!("{}", serde_yaml::to_string(&completed_targets[idx])?);
+        }
+        Command::Edit {
+            entry,
+            hit,
+            score,
+            notes,
+            transcript,
+        } => {
+            if hit.is_none() && score.is_none() && notes.is_none() && transcript.is_none() {
+                anyhow::bail!("nothing to change, pass --hit, --score, --notes or --transcript");
+            }
+            let hit = hit.map(|h| parse_hit(&h)).transpose()?;
+            let score = score.map(|s| parse_score(&s)).transpose()?;
+            let transcript = transcript.as_deref().map(persist::read_input).transpose()?;
+            CompletedTarget::update(&args.completed, |completed_targets| {
+                let idx = resolve(completed_targets, &entry)?;
+                let ct = &mut completed_targets[idx];
+                if let Some(hit) = hit {
+                    ct.hit = hit;
+                }
+                if let Some(score) = score {
+                    ct.score = score;
+                }
+                if let Some(notes) = notes {
+                    ct.notes = Some(notes).filter(|n| !n.trim().is_empty());
+                }
+                if let Some(text) = transcript {
+                    ct.transcript = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+                }
+                info!("Edited {}", summary(idx, ct));
+                Ok(())
+            })?;
+        }
+        Command::Reopen { entry } => {
+            CompletedTarget::update(&args.completed, |completed_targets| {
+                let idx = resolve(completed_targets, &entry)?;
+                let ct = &mut completed_targets[idx];
+                if ct.reopened_at.is_some() {
+                    anyhow::bail!("entry {} ({}) is already reopened", idx + 1, ct.rvuid);
+                }
+                ct.reopened_at = Some(Utc::now());
+                info!("Reopened {}, it can be picked again.", ct.rvuid);
+                Ok(())
+            })?;
+        }
+        Command::Remove { entry } => {
+            CompletedTarget::update(&args.completed, |completed_targets| {
+                let idx = resolve(completed_targets, &entry)?;
+                let ct = completed_targets.remove(idx);
+                info!("Removed {}", summary(idx, &ct));
+                Ok(())
+            })?;
+        }
+        Command::Compact => {
+            journal(&args.completed)?.compact()?;
+        }
+        Command::Import { yaml } => {
+            let journal = journal(&args.completed)?;
+            if Journal::is_journal(&yaml) {
+                anyhow::bail!("{} is already a journal", yaml);
+            }
+            let completed_targets = CompletedTarget::parse(&yaml)?;
+            let imported = journal.import(&completed_targets)?;
+            info!(
+                "Imported {} of {} completed targets into {}",
+                imported,
+                completed_targets.len(),
+                journal.path.display()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use clap::Parser;
+use log::info;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use psitool::config::Config;
+use psitool::export::{self, ExportRow, Filter, Format};
+use psitool::logger;
+use psitool::target::{CompletedTarget, parse_hit};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools and viewer profiles"
+    )]
+    config: String,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value = "~/.psitool_completed_targets.yaml",
+        help = "the yaml config with a list of completed targets (used to cache what you RV'd already)"
+    )]
+    completed: String,
+
+    #[arg(
+        short = 'u',
+        long = "viewer",
+        conflicts_with = "completed",
+        help = "a viewer profile from the config whose completed targets to export (repeat for each)"
+    )]
+    viewers: Vec<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["completed", "viewers"],
+        help = "export the completed targets of every viewer in the config"
+    )]
+    all_viewers: bool,
+
+    #[arg(
+        long,
+        default_value = "csv",
+        help = "the output format (csv, json, jsonl)"
+    )]
+    format: Format,
+
+    #[arg(short, long, help = "the file to write to (stdout by default)")]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        help = "only sessions completed on or after this date (YYYY-MM-DD)"
+    )]
+    since: Option<String>,
+
+    #[arg(
+        long,
+        help = "only sessions completed on or before this date (YYYY-MM-DD)"
+    )]
+    until: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "only targets from this named pool (repeat for several)"
+    )]
+    pools: Vec<String>,
+
+    #[arg(
+        long,
+        help = "only sessions that were a hit (yes), a miss (no), or weren't marked (unknown)"
+    )]
+    hit: Option<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    let cfg = Config::load(&args.config)?;
+    for pool in &args.pools {
+        if !cfg.has_pool(pool) {
+            anyhow::bail!(
+                "no pool '{}' in the config ({})",
+                pool,
+                cfg.list_pools().join(", ")
+            );
+        }
+    }
+    let filter = Filter {
+        since: args.since.as_deref().map(export::parse_date).transpose()?,
+        until: args.until.as_deref().map(export::parse_date).transpose()?,
+        pools: args.pools.clone(),
+        hit: match args.hit.as_deref() {
+            None => None,
+            Some("unknown") => Some(None),
+            Some(h) => match parse_hit(h)? {
+                Some(hit) => Some(Some(hit)),
+                None => anyhow::bail!("--hit should be yes, no or unknown"),
+            },
+        },
+    };
+
+    let viewers = if args.all_viewers {
+        cfg.list_viewers()
+    } else {
+        args.viewers.clone()
+    };
+    let mut sources: Vec<(Option<&str>, String)> = Vec::new();
+    if viewers.is_empty() {
+        sources.push((None, args.completed.clone()));
+    }
+    for name in &viewers {
+        sources.push((Some(name.as_str()), cfg.get_viewer(name)?.completed.clone()));
+    }
+
+    let mut rows = Vec::new();
+    for (viewer, completed) in &sources {
+        for ct in CompletedTarget::parse(completed)? {
+            let row = ExportRow::new(*viewer, &ct, &cfg);
+            if filter.matches(&row) {
+                rows.push(row);
+            }
+        }
+    }
+
+    match &args.output {
+        Some(path) => {
+            let path = shellexpand::tilde(path).into_owned();
+            let mut out = BufWriter::new(File::create(&path)?);
+            export::write(&rows, args.format, &mut out)?;
+            out.flush()?;
+            info!("Wrote {} rows to {}", rows.len(), path);
+        }
+        None => {
+            let mut out = io::stdout().lock();
+            export::write(&rows, args.format, &mut out)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
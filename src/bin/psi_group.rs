@@ -0,0 +1,315 @@
+use chrono::Utc;
+use clap::{ArgAction, Parser, Subcommand};
+use log::{debug, info};
+
+use psitool::cache::{CacheMap, CachedHash};
+use psitool::config::{Config, TargetPool, random_pool};
+use psitool::group::{Consensus, Group};
+use psitool::journal::new_session_id;
+use psitool::logger;
+use psitool::persist;
+use psitool::preview::PreviewMode;
+use psitool::rvuid::Rvuid;
+use psitool::target::{CompletedTarget, Target, parse_hit};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools and viewer profiles"
+    )]
+    config: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_cached_hashes.yaml",
+        help = "the yaml config with a list of cached hashes so it doesn't have to compute them every run"
+    )]
+    cached_hashes: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_groups",
+        help = "the directory group sessions are kept in"
+    )]
+    groups: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Pick one target for a group, none of whom have done it yet
+    Start {
+        #[arg(
+            short = 'u',
+            long = "viewer",
+            help = "a viewer profile taking part (repeat for each, all viewers in the config by default)"
+        )]
+        viewers: Vec<String>,
+
+        #[arg(short, action = ArgAction::Count, help = "how much to frontload, none by default (pass -f for 1 level of frontloading, -ff for 2, -fff for 3...)")]
+        frontload: u8,
+
+        #[arg(
+            short,
+            long,
+            help = "the named target pool to read from (included unless excluded via label)"
+        )]
+        pools: Vec<String>,
+
+        #[arg(
+            short = 'i',
+            long,
+            help = "the target pools to read from, including this label"
+        )]
+        include_label: Option<String>,
+
+        #[arg(
+            short = 'x',
+            long,
+            help = "the target pools to read from, EXCLUDING this label"
+        )]
+        exclude_label: Option<String>,
+    },
+    /// Record one viewer's results, in their own completed targets
+    Submit {
+        #[arg(help = "the group session ID from `start`")]
+        group: String,
+
+        #[arg(short = 'u', long, help = "the viewer submitting")]
+        viewer: String,
+
+        #[arg(long, help = "a file with the session transcript (- for stdin)")]
+        transcript: Option<String>,
+
+        #[arg(
+            long,
+            help = "whether it was a hit (y/yes/n/no), usually after the report"
+        )]
+        hit: Option<String>,
+
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u32).range(0..=100),
+            help = "the score out of 100, usually after the report"
+        )]
+        score: Option<u32>,
+
+        #[arg(long, help = "notes about the session")]
+        notes: Option<String>,
+    },
+    /// Reveal the target and compare everyone's results, once they've all submitted
+    Report {
+        #[arg(help = "the group session ID from `start`")]
+        group: String,
+
+        #[arg(long, help = "report even if not every viewer has submitted")]
+        force: bool,
+
+        #[arg(short, long, help = "dont open the target")]
+        skip_open: bool,
+
+        #[arg(
+            short = 'P',
+            long,
+            default_value = "auto",
+            help = "how to draw image targets in the terminal when they aren't opened (auto, kitty, sixel, blocks, none)"
+        )]
+        preview: PreviewMode,
+    },
+}
+
+/// Each viewer's entry for the group, if they've submitted.
+fn group_entries(
+    cfg: &Config,
+    group: &Group,
+) -> anyhow::Result<Vec<(String, Option<CompletedTarget>)>> {
+    let mut entries = Vec::new();
+    for name in &group.viewers {
+        let viewer = cfg.get_viewer(name)?;
+        let entry = CompletedTarget::parse(&viewer.completed)?
+            .into_iter()
+            .rfind(|ct| ct.group.as_deref() == Some(group.id.as_str()));
+        entries.push((name.clone(), entry));
+    }
+    Ok(entries)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    let cfg = Config::load(&args.config)?;
+    match args.command {
+        Command::Start {
+            viewers,
+            frontload,
+            pools,
+            include_label,
+            exclude_label,
+        } => {
+            let viewers = if viewers.is_empty() {
+                cfg.list_viewers()
+            } else {
+                viewers
+            };
+            if viewers.is_empty() {
+                anyhow::bail!("no viewers, add some to the config or pass --viewer");
+            }
+            let mut completed_rvuids: Vec<Rvuid> = Vec::new();
+            for name in &viewers {
+                let theirs = CompletedTarget::parse(&cfg.get_viewer(name)?.completed)?;
+                completed_rvuids.extend(CompletedTarget::completed_rvuids(&theirs));
+            }
+            let mut cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
+            let tpools: Vec<&TargetPool> =
+                cfg.select_pools(&pools, include_label.as_deref(), exclude_label.as_deref())?;
+            let tpool = random_pool(&tpools, &completed_rvuids, &mut cachemap)?;
+            let target = tpool.random_target(&completed_rvuids, &mut cachemap)?;
+            CachedHash::dump(&cachemap, &args.cached_hashes)?;
+            let range_end = target.frontloading.len().min(frontload as usize);
+            let group = Group {
+                id: new_session_id(),
+                rvuid: target.rvuid.clone(),
+                path: target.path.clone(),
+                viewers,
+                frontloading: target.frontloading[..range_end].to_vec(),
+                started_at: Utc::now(),
+            };
+            group.save(&args.groups)?;
+            debug!("Chose rvuid {} for group {}", group.rvuid, group.id);
+            println!(
+                "Group session {} with {}",
+                group.id,
+                group.viewers.join(", ")
+            );
+            println!("Target: {}", group.rvuid);
+            if frontload > 0 {
+                println!("Frontloading: {:?}", group.frontloading);
+            }
+            println!(
+                "Each viewer submits with: psi-group submit {} -u <viewer> --transcript <file>",
+                group.id
+            );
+        }
+        Command::Submit {
+            group,
+            viewer,
+            transcript,
+            hit,
+            score,
+            notes,
+        } => {
+            let group = Group::load(&args.groups, &group)?;
+            if !group.viewers.contains(&viewer) {
+                anyhow::bail!(
+                    "{} isn't part of group {} ({})",
+                    viewer,
+                    group.id,
+                    group.viewers.join(", ")
+                );
+            }
+            let hit = hit.map(|h| parse_hit(&h)).transpose()?;
+            let transcript = transcript.as_deref().map(persist::read_input).transpose()?;
+            let mut new_entry = CompletedTarget::from(Target::parse(&group.path)?);
+            new_entry.group = Some(group.id.clone());
+            let completed = &cfg.get_viewer(&viewer)?.completed;
+            CompletedTarget::update(completed, |completed_targets| {
+                let ct = match completed_targets
+                    .iter()
+                    .rposition(|ct| ct.group.as_deref() == Some(group.id.as_str()))
+                {
+                    Some(idx) => &mut completed_targets[idx],
+                    None => {
+                        completed_targets.push(new_entry);
+                        completed_targets.last_mut().unwrap()
+                    }
+                };
+                if let Some(hit) = hit {
+                    ct.hit = hit;
+                }
+                if score.is_some() {
+                    ct.score = score;
+                }
+                if let Some(notes) = notes {
+                    ct.notes = Some(notes).filter(|n| !n.trim().is_empty());
+                }
+                if let Some(text) = transcript {
+                    ct.transcript = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+                }
+                Ok(())
+            })?;
+            let entries = group_entries(&cfg, &group)?;
+            let submitted = entries.iter().filter(|(_, e)| e.is_some()).count();
+            info!(
+                "Recorded {}'s results for group {} ({}/{} submitted)",
+                viewer,
+                group.id,
+                submitted,
+                entries.len()
+            );
+        }
+        Command::Report {
+            group,
+            force,
+            skip_open,
+            preview,
+        } => {
+            let group = Group::load(&args.groups, &group)?;
+            let entries = group_entries(&cfg, &group)?;
+            let missing: Vec<&str> = entries
+                .iter()
+                .filter(|(_, e)| e.is_none())
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if !missing.is_empty() && !force {
+                anyhow::bail!(
+                    "still waiting on {} (pass --force to report anyway)",
+                    missing.join(", ")
+                );
+            }
+            println!("Group session {}: {}", group.id, group.rvuid);
+            let _components = Target::parse(&group.path)?.reveal(skip_open, preview)?;
+            let submitted: Vec<(String, &CompletedTarget)> = entries
+                .iter()
+                .filter_map(|(name, e)| e.as_ref().map(|ct| (name.clone(), ct)))
+                .collect();
+            let consensus = Consensus::new(&submitted);
+            println!();
+            println!("Scores:");
+            for (name, entry) in &entries {
+                let score = match entry {
+                    Some(ct) => ct.score.map(|s| s.to_string()).unwrap_or("-".to_string()),
+                    None => "not submitted".to_string(),
+                };
+                println!("  {}: {}", name, score);
+            }
+            if let Some((min, max, mean, stddev)) = consensus.score_spread() {
+                println!(
+                    "  min {}, max {}, mean {:.1}, standard deviation {:.1}",
+                    min, max, mean, stddev
+                );
+            }
+            println!();
+            if consensus.shared.is_empty() {
+                println!("No descriptors shared by more than one viewer.");
+            } else {
+                println!("Shared descriptors:");
+                for (word, viewers) in &consensus.shared {
+                    println!("  {} ({}): {}", word, viewers.len(), viewers.join(", "));
+                }
+            }
+        }
+    }
+    Ok(())
+}
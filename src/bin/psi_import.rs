@@ -0,0 +1,348 @@
+use anyhow::Context;
+use clap::Parser;
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
+
+use psitool::cache::{CacheMap, CachedHash};
+use psitool::config::Config;
+use psitool::export::Format;
+use psitool::import::{self, ImportRow, Mapping, Merged};
+use psitool::logger;
+use psitool::rvuid::Rvuid;
+use psitool::target::{CompletedTarget, Target};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools (this is where it will look for targets)"
+    )]
+    config: String,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value = "~/.psitool_completed_targets.yaml",
+        help = "the yaml config with a list of completed targets (used to cache what you RV'd already)"
+    )]
+    completed: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_cached_hashes.yaml",
+        help = "the yaml config with a list of cached hashes so it doesn't have to compute them every run"
+    )]
+    cached_hashes: String,
+
+    #[arg(
+        short = 'u',
+        long,
+        conflicts_with = "completed",
+        help = "the viewer profile from the config whose completed targets to import into"
+    )]
+    viewer: Option<String>,
+
+    #[arg(help = "the CSV, JSON or JSON Lines file of sessions to import")]
+    input: String,
+
+    #[arg(
+        long,
+        help = "the input format (csv, json, jsonl), from the extension by default"
+    )]
+    format: Option<Format>,
+
+    #[arg(
+        short,
+        long,
+        help = "a yaml file mapping fields to your column names (rvuid, path, completed_at, hit, score, notes, transcript, date_format)"
+    )]
+    mapping: Option<String>,
+
+    #[arg(
+        long = "map",
+        help = "map one field to a column, like score=\"Score (%)\" (repeat for several, overrides --mapping)"
+    )]
+    maps: Vec<String>,
+
+    #[arg(
+        long,
+        help = "the directory relative target paths are relative to (the input file's directory by default)"
+    )]
+    base_dir: Option<String>,
+
+    #[arg(short = 'n', long, help = "only show what would change")]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        help = "overwrite the results of sessions already recorded with different ones"
+    )]
+    update: bool,
+
+    #[arg(long, help = "import the rows that resolve even if some don't")]
+    skip_errors: bool,
+}
+
+/// Finds the target each imported row is about.
+struct Resolver<'a> {
+    cfg: &'a Config,
+    cachemap: CacheMap,
+    completed_targets: &'a [CompletedTarget],
+    base_dir: PathBuf,
+    pool_targets: Option<Vec<CachedHash>>,
+}
+
+impl Resolver<'_> {
+    fn pool_targets(&mut self) -> anyhow::Result<&[CachedHash]> {
+        if self.pool_targets.is_none() {
+            let mut names = self.cfg.list_pools();
+            names.sort();
+            let mut all = Vec::new();
+            for name in names {
+                all.extend(
+                    self.cfg
+                        .get_pool(&name)
+                        .unwrap()
+                        .all_targets(&mut self.cachemap)?,
+                );
+            }
+            self.pool_targets = Some(all);
+        }
+        Ok(self.pool_targets.as_deref().unwrap())
+    }
+
+    /// The completed entry for a row: from its file if it still exists, or else from its RVUID
+    /// or file name, looked up in the completed targets and the pools.
+    fn resolve(&mut self, row: &ImportRow) -> anyhow::Result<CompletedTarget> {
+        let rvuid: Option<Rvuid> = row.rvuid.as_deref().map(|s| s.trim().parse()).transpose()?;
+        if let Some(ref path) = row.path {
+            let path = self
+                .base_dir
+                .join(shellexpand::tilde(path.trim()).into_owned());
+            if path.is_file() || path.is_dir() {
+                // Stored as is in the completed target, so it has to be the real, absolute path
+                // for pools and history to match it later.
+                let path = path
+                    .canonicalize()
+                    .with_context(|| format!("failed to resolve {}", path.display()))?;
+                let target = Target::parse(&path)?;
+                if let Some(ref rvuid) = rvuid
+                    && *rvuid != target.rvuid
+                {
+                    anyhow::bail!(
+                        "{} is {}, not {} as the row says",
+                        path.display(),
+                        target.rvuid,
+                        rvuid
+                    );
+                }
+                return Ok(CompletedTarget::from(target));
+            }
+            if rvuid.is_none() {
+                return self.by_file_name(&path);
+            }
+        }
+        let Some(rvuid) = rvuid else {
+            anyhow::bail!("no RVUID or target path");
+        };
+        let mut found: Vec<CompletedTarget> = Vec::new();
+        for ct in self.completed_targets.iter().filter(|ct| ct.rvuid == rvuid) {
+            if !found.iter().any(|f| f.rvuid.uuid == ct.rvuid.uuid) {
+                found.push(ct.clone());
+            }
+        }
+        if rvuid.missing_bits || found.is_empty() {
+            for ch in self.pool_targets()? {
+                if ch.rvuid == rvuid && !found.iter().any(|f| f.rvuid.uuid == ch.rvuid.uuid) {
+                    found.push(CompletedTarget::from(Target::parse(&ch.path)?));
+                }
+            }
+        }
+        match found.len() {
+            0 => anyhow::bail!("no target found for {}", rvuid),
+            1 => Ok(found.pop().unwrap()),
+            _ => {
+                let paths: Vec<String> = found
+                    .iter()
+                    .map(|ct| format!("{} at {}", ct.rvuid, ct.path))
+                    .collect();
+                anyhow::bail!("{} is ambiguous: {}", rvuid, paths.join(", "))
+            }
+        }
+    }
+
+    /// For spreadsheets that only kept the target's file name.
+    fn by_file_name(&mut self, path: &Path) -> anyhow::Result<CompletedTarget> {
+        let Some(name) = path.file_name() else {
+            anyhow::bail!("{} doesn't exist", path.display());
+        };
+        let matches: Vec<PathBuf> = self
+            .pool_targets()?
+            .iter()
+            .filter(|ch| ch.path.file_name() == Some(name))
+            .map(|ch| ch.path.clone())
+            .collect();
+        match matches.as_slice() {
+            [] => anyhow::bail!("{} doesn't exist and isn't in any pool", path.display()),
+            [found] => Ok(CompletedTarget::from(Target::parse(found)?)),
+            _ => anyhow::bail!(
+                "{} doesn't exist and is in more than one pool, add the RVUID column",
+                path.display()
+            ),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    let cfg = Config::load(&args.config)?;
+    if let Some(ref name) = args.viewer {
+        args.completed = cfg.get_viewer(name)?.completed.clone();
+    }
+    let input = PathBuf::from(shellexpand::tilde(&args.input).into_owned());
+    let format = match args.format {
+        Some(format) => format,
+        None => input
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| {
+                anyhow::anyhow!("can't tell the format of {}, pass --format", args.input)
+            })?,
+    };
+    let mut mapping = match args.mapping {
+        Some(ref path) => Mapping::load(path)?,
+        None => Mapping::default(),
+    };
+    for spec in &args.maps {
+        mapping.set(spec)?;
+    }
+    let base_dir = match args.base_dir {
+        Some(ref dir) => PathBuf::from(shellexpand::tilde(dir).into_owned()),
+        None => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    // A bare `sessions.csv` has an empty parent.
+    let base_dir = if base_dir.as_os_str().is_empty() {
+        std::env::current_dir()?
+    } else {
+        std::path::absolute(&base_dir)?
+    };
+
+    let raw_rows = import::read_rows(&input, format)?;
+    let completed_targets = CompletedTarget::parse(&args.completed)?;
+    let mut resolver = Resolver {
+        cfg: &cfg,
+        cachemap: CachedHash::parse(&args.cached_hashes)?,
+        completed_targets: &completed_targets,
+        base_dir,
+        pool_targets: None,
+    };
+    let mut imported: Vec<(usize, CompletedTarget)> = Vec::new();
+    let mut errors = 0;
+    for (idx, raw) in raw_rows.iter().enumerate() {
+        // The row number as a spreadsheet shows it, under the header.
+        let n = if format == Format::Csv {
+            idx + 2
+        } else {
+            idx + 1
+        };
+        let resolved = mapping.apply(raw).and_then(|row| {
+            let mut ct = resolver.resolve(&row)?;
+            ct.hit = row.hit;
+            ct.score = row.score;
+            ct.notes = row.notes;
+            ct.transcript = row.transcript;
+            ct.completed_at = row.completed_at;
+            ct.reopened_at = None;
+            ct.session = None;
+            ct.group = None;
+            Ok(ct)
+        });
+        match resolved {
+            Ok(ct) => {
+                debug!("Row {} is {} at {}", n, ct.rvuid, ct.path);
+                imported.push((n, ct));
+            }
+            Err(e) => {
+                println!("! row {}: {:#}", n, e);
+                errors += 1;
+            }
+        }
+    }
+    CachedHash::dump(&resolver.cachemap, &args.cached_hashes)?;
+    if errors > 0 && !args.dry_run && !args.skip_errors {
+        anyhow::bail!(
+            "{} of {} rows couldn't be imported, fix them or pass --skip-errors",
+            errors,
+            raw_rows.len()
+        );
+    }
+
+    let report = |results: &[(usize, &CompletedTarget, Merged)]| {
+        let (mut added, mut changed, mut duplicates) = (0, 0, 0);
+        for (n, ct, merged) in results {
+            let when = ct
+                .completed_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or("-".to_string());
+            match merged {
+                Merged::Added => {
+                    added += 1;
+                    println!("+ row {}: {}  {}  {}", n, ct.rvuid, when, ct.path);
+                }
+                Merged::Duplicate => {
+                    duplicates += 1;
+                    println!("= row {}: {}  {}  already recorded", n, ct.rvuid, when);
+                }
+                Merged::Changed(changes) => {
+                    changed += 1;
+                    println!(
+                        "~ row {}: {}  {}  {}",
+                        n,
+                        ct.rvuid,
+                        when,
+                        changes.join(", ")
+                    );
+                }
+            }
+        }
+        (added, changed, duplicates)
+    };
+
+    let merge_all = |current: &mut Vec<CompletedTarget>| {
+        imported
+            .iter()
+            .map(|(n, ct)| (*n, ct, import::merge(current, ct.clone(), args.update)))
+            .collect::<Vec<_>>()
+    };
+    let results = if args.dry_run {
+        merge_all(&mut completed_targets.clone())
+    } else {
+        CompletedTarget::update(&args.completed, |current| Ok(merge_all(current)))?
+    };
+    let (added, changed, duplicates) = report(&results);
+    let verb = if args.dry_run {
+        "Would import"
+    } else {
+        "Imported"
+    };
+    info!(
+        "{} {} new sessions into {} ({} already recorded, {} with different results, {} errors)",
+        verb, added, args.completed, duplicates, changed, errors
+    );
+    if changed > 0 && !args.update {
+        warn!("Pass --update to overwrite the results that differ");
+    }
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use clap::{Parser, Subcommand};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use psitool::bundle::Bundle;
+use psitool::cache::{CacheMap, CachedHash};
+use psitool::config::Config;
+use psitool::credits::{self, Credit, Format};
+use psitool::license::{License, Policy};
+use psitool::logger;
+use psitool::target::{TargetType, YamlData};
+use psitool::validate;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools"
+    )]
+    config: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_cached_hashes.yaml",
+        help = "the yaml config with a list of cached hashes so it doesn't have to compute them every run"
+    )]
+    cached_hashes: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check every target in the pools for corrupted or mislabeled files
+    Check {
+        #[arg(
+            long,
+            help = "move invalid files (and their YAML) into a quarantine subfolder of the pool"
+        )]
+        quarantine: bool,
+
+        #[arg(help = "the target pools to check (all pools if none passed)")]
+        pools: Vec<String>,
+    },
+    /// Audit the licenses in the targets' metadata against each pool's license policy
+    Licenses {
+        #[arg(
+            long,
+            help = "also require CC0, CC-BY, or PUBLIC DOMAIN, like psi-wm-downloader --free-only"
+        )]
+        free_only: bool,
+
+        #[arg(short, long, help = "write the non-compliant files to this CSV report")]
+        output: Option<String>,
+
+        #[arg(help = "the target pools to audit (all pools if none passed)")]
+        pools: Vec<String>,
+    },
+    /// Write an attribution file crediting the authors of a pool's targets, and optionally package
+    /// the pool to share
+    Credits {
+        #[arg(
+            long,
+            help = "only take CC0, CC-BY, and PUBLIC DOMAIN, like psi-wm-downloader --free-only"
+        )]
+        free_only: bool,
+
+        #[arg(
+            long,
+            default_value = "markdown",
+            help = "the attribution file's format (markdown, html, text)"
+        )]
+        format: Format,
+
+        #[arg(
+            short,
+            long,
+            help = "write the attribution file here (printed if neither this nor --archive is passed)"
+        )]
+        output: Option<String>,
+
+        #[arg(
+            long,
+            help = "zip the credited targets, their YAML and the attribution file into this archive"
+        )]
+        archive: Option<String>,
+
+        #[arg(help = "the target pool to credit")]
+        pool: String,
+    },
+}
+
+/// A target whose license its pool doesn't allow.
+#[derive(Serialize)]
+struct Noncompliant {
+    pool: String,
+    path: String,
+    license: String,
+    reason: String,
+}
+
+fn report_invalid(
+    path: &Path,
+    err: &anyhow::Error,
+    quarantine: bool,
+    cachemap: &mut CacheMap,
+) -> anyhow::Result<()> {
+    println!("INVALID {}: {:#}", path.display(), err);
+    if quarantine {
+        cachemap.remove(&path.to_path_buf());
+        let dest = validate::quarantine(path)?;
+        println!("  moved to {}", dest.display());
+    }
+    Ok(())
+}
+
+fn check(
+    cfg: &Config,
+    pools: &[String],
+    quarantine: bool,
+    cachemap: &mut CacheMap,
+) -> anyhow::Result<usize> {
+    let mut invalid = 0usize;
+    for pool in pools {
+        let Some(tpool) = cfg.get_pool(pool) else {
+            anyhow::bail!("couldnt find pool '{}'", pool);
+        };
+        let dir = tpool.dest_dir()?;
+        info!("Checking pool '{}' at {}", pool, dir.display());
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() || Bundle::is_bundle_dir(p))
+            .collect();
+        paths.sort();
+        let mut checked = 0usize;
+        for path in paths {
+            if !path.exists() {
+                // A sidecar that was quarantined along with its target.
+                continue;
+            }
+            if path.is_dir() {
+                checked += 1;
+                if let Err(e) = Bundle::load(&path) {
+                    invalid += 1;
+                    report_invalid(&path, &e, quarantine, cachemap)?;
+                }
+                continue;
+            }
+            let bytes = fs::read(&path)?;
+            let result = match TargetType::detect(&path, &bytes) {
+                Some(target_type) => validate::validate_bytes(&bytes, &target_type),
+                // Named like a target but the contents are something else entirely.
+                None if TargetType::parse(&path).is_some() => {
+                    Err(anyhow::anyhow!("not a recognised target type"))
+                }
+                None => {
+                    debug!("Skipping non-target {}", path.display());
+                    continue;
+                }
+            };
+            checked += 1;
+            let Err(e) = result else {
+                continue;
+            };
+            invalid += 1;
+            report_invalid(&path, &e, quarantine, cachemap)?;
+        }
+        info!("Checked {} files in pool '{}'", checked, pool);
+    }
+    Ok(invalid)
+}
+
+fn audit_licenses(
+    cfg: &Config,
+    pools: &[String],
+    free_only: bool,
+) -> anyhow::Result<Vec<Noncompliant>> {
+    let mut found = Vec::new();
+    for pool in pools {
+        let policies = policies(cfg, pool, free_only)?;
+        if policies.is_empty() {
+            info!("Pool '{}' has no license policy, skipping it", pool);
+            continue;
+        }
+        let dir = cfg.get_pool(pool).unwrap().dest_dir()?;
+        info!("Auditing licenses in pool '{}' at {}", pool, dir.display());
+        let paths = target_paths(&dir)?;
+        for path in &paths {
+            let meta = YamlData::load_for(path)?;
+            let license = match &meta {
+                Some(meta) => License::of(meta),
+                None => License::parse("", None),
+            };
+            debug!("{}: {:?}", path.display(), license);
+            for policy in &policies {
+                if let Err(reason) = policy.check(&license) {
+                    println!("NONCOMPLIANT {}: {}", path.display(), reason);
+                    found.push(Noncompliant {
+                        pool: pool.clone(),
+                        path: path.display().to_string(),
+                        license: meta.as_ref().map(|m| m.license.clone()).unwrap_or_default(),
+                        reason,
+                    });
+                    break;
+                }
+            }
+        }
+        info!("Audited {} targets in pool '{}'", paths.len(), pool);
+    }
+    Ok(found)
+}
+
+/// The files and bundle directories in a pool that look like targets, sorted. Hidden entries
+/// (like the `.partial` downloads and the download state) are skipped.
+fn target_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            let hidden = p
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with('.'));
+            if hidden {
+                false
+            } else if p.is_dir() {
+                Bundle::is_bundle_dir(p)
+            } else {
+                TargetType::parse(p).is_some()
+            }
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn policies(cfg: &Config, pool: &str, free_only: bool) -> anyhow::Result<Vec<Policy>> {
+    let Some(tpool) = cfg.get_pool(pool) else {
+        anyhow::bail!("couldnt find pool '{}'", pool);
+    };
+    Ok(tpool.license_policies(free_only))
+}
+
+fn credits(
+    cfg: &Config,
+    pool: &str,
+    free_only: bool,
+) -> anyhow::Result<(Vec<Credit>, Vec<PathBuf>)> {
+    let policies = policies(cfg, pool, free_only)?;
+    let dir = cfg.get_pool(pool).unwrap().dest_dir()?;
+    info!("Crediting targets in pool '{}' at {}", pool, dir.display());
+    let (mut found, mut paths, mut refused) = (Vec::new(), Vec::new(), 0usize);
+    for path in target_paths(&dir)? {
+        let meta = YamlData::load_for(&path)?;
+        let checked = Credit::new(&path, meta.as_ref()).and_then(|credit| {
+            let license = License::of(meta.as_ref().unwrap());
+            policies
+                .iter()
+                .try_for_each(|policy| policy.check(&license))
+                .map(|_| credit)
+        });
+        match checked {
+            Ok(credit) => {
+                found.push(credit);
+                paths.push(path);
+            }
+            Err(reason) => {
+                // Logged rather than printed, since the attribution may be going to stdout.
+                warn!("REFUSED {}: {}", path.display(), reason);
+                refused += 1;
+            }
+        }
+    }
+    if refused > 0 {
+        warn!("Left out {} targets that can't be shared", refused);
+    }
+    Ok((found, paths))
+}
+
+/// The pools named, or every pool if none were.
+fn pools_or_all(cfg: &Config, pools: Vec<String>) -> Vec<String> {
+    if pools.is_empty() {
+        let mut all = cfg.list_pools();
+        all.sort();
+        all
+    } else {
+        pools
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    let cfg = Config::load(&args.config)?;
+    let mut cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
+    match args.command {
+        Command::Check { quarantine, pools } => {
+            let pools = pools_or_all(&cfg, pools);
+            let invalid = check(&cfg, &pools, quarantine, &mut cachemap)?;
+            if quarantine {
+                CachedHash::dump(&cachemap, &args.cached_hashes)?;
+                info!("Quarantined {} invalid targets.", invalid);
+            } else if invalid > 0 {
+                warn!("Pass --quarantine to move them out of the pool.");
+                anyhow::bail!("found {} invalid targets", invalid);
+            } else {
+                info!("All targets are valid.");
+            }
+        }
+        Command::Licenses {
+            free_only,
+            output,
+            pools,
+        } => {
+            let pools = pools_or_all(&cfg, pools);
+            let found = audit_licenses(&cfg, &pools, free_only)?;
+            if let Some(output) = output {
+                let output = shellexpand::tilde(&output).into_owned();
+                let mut writer = csv::Writer::from_path(&output)?;
+                for row in &found {
+                    writer.serialize(row)?;
+                }
+                writer.flush()?;
+                info!("Wrote the report to {}", output);
+            }
+            if !found.is_empty() {
+                anyhow::bail!(
+                    "found {} targets with licenses their pool doesn't allow",
+                    found.len()
+                );
+            }
+            info!("All licenses are allowed.");
+        }
+        Command::Credits {
+            free_only,
+            format,
+            output,
+            archive,
+            pool,
+        } => {
+            let (found, paths) = credits(&cfg, &pool, free_only)?;
+            if found.is_empty() {
+                anyhow::bail!("no targets in pool '{}' can be shared", pool);
+            }
+            let attribution = credits::render(&pool, &found, format);
+            if let Some(output) = &output {
+                let output = shellexpand::tilde(output).into_owned();
+                fs::write(&output, &attribution)?;
+                info!(
+                    "Wrote the attribution for {} targets to {}",
+                    found.len(),
+                    output
+                );
+            }
+            if let Some(archive) = &archive {
+                let archive = shellexpand::tilde(archive).into_owned();
+                credits::write_archive(
+                    Path::new(&archive),
+                    &pool,
+                    &paths,
+                    (format.file_name(), &attribution),
+                )?;
+                info!("Packaged {} targets into {}", paths.len(), archive);
+            }
+            if output.is_none() && archive.is_none() {
+                print!("{}", attribution);
+            }
+        }
+    }
+    Ok(())
+}
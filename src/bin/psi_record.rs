@@ -0,0 +1,168 @@
+use clap::Parser;
+use log::{debug, info};
+
+use psitool::cache::{CacheMap, CachedHash};
+use psitool::config::Config;
+use psitool::logger;
+use psitool::persist;
+use psitool::rvuid::Rvuid;
+use psitool::target::{CompletedTarget, Target, parse_hit};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(long, help = "whether it was a hit (y/yes/n/no, or blank to clear it)")]
+    hit: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(0..=100),
+        help = "the score out of 100"
+    )]
+    score: Option<u32>,
+
+    #[arg(long, help = "notes about the session")]
+    notes: Option<String>,
+
+    #[arg(
+        long,
+        help = "a file with the session transcript (- for stdin), e.g. your typed up notes from paper"
+    )]
+    transcript: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools (this is where it will look for the RVUID)"
+    )]
+    config: String,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value = "~/.psitool_completed_targets.yaml",
+        help = "the yaml config with a list of completed targets (used to cache what you RV'd already)"
+    )]
+    completed: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_cached_hashes.yaml",
+        help = "the yaml config with a list of cached hashes so it doesn't have to compute them every run"
+    )]
+    cached_hashes: String,
+
+    #[arg(
+        short = 'u',
+        long,
+        conflicts_with = "completed",
+        help = "the viewer profile from the config whose completed targets to use"
+    )]
+    viewer: Option<String>,
+
+    #[arg(
+        help = "the RVUID of the target, either in full or just the first 40 bits (R-XXXX-XXXX)"
+    )]
+    rvuid: Rvuid,
+}
+
+/// The full RVUIDs the one given could refer to, with where each was found.
+fn candidates(
+    args: &Args,
+    completed_targets: &[CompletedTarget],
+    cachemap: &mut CacheMap,
+) -> anyhow::Result<Vec<(Rvuid, String)>> {
+    let mut found: Vec<(Rvuid, String)> = Vec::new();
+    for ct in completed_targets.iter().filter(|ct| ct.rvuid == args.rvuid) {
+        if !found.iter().any(|(rv, _)| rv.uuid == ct.rvuid.uuid) {
+            found.push((ct.rvuid.clone(), ct.path.clone()));
+        }
+    }
+    // A full RVUID that's already recorded can't be ambiguous, so there's no need to scan pools.
+    if !args.rvuid.missing_bits && !found.is_empty() {
+        return Ok(found);
+    }
+    let cfg = Config::load(&args.config)?;
+    for ch in cfg.find_rvuid(&args.rvuid, cachemap)? {
+        if !found.iter().any(|(rv, _)| rv.uuid == ch.rvuid.uuid) {
+            found.push((ch.rvuid, ch.path.display().to_string()));
+        }
+    }
+    Ok(found)
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    if let Some(ref name) = args.viewer {
+        args.completed = Config::load(&args.config)?
+            .get_viewer(name)?
+            .completed
+            .clone();
+    }
+    let completed_targets: Vec<CompletedTarget> = CompletedTarget::parse(&args.completed)?;
+    let mut cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
+    let found = candidates(&args, &completed_targets, &mut cachemap)?;
+    CachedHash::dump(&cachemap, &args.cached_hashes)?;
+    let (rvuid, path) = match found.len() {
+        0 => anyhow::bail!("no target found for {}", args.rvuid),
+        1 => found.into_iter().next().unwrap(),
+        _ => {
+            let list: Vec<String> = found
+                .iter()
+                .map(|(rv, path)| format!("  {} at {}", rv, path))
+                .collect();
+            anyhow::bail!(
+                "{} is ambiguous, pass the full RVUID of one of:\n{}",
+                args.rvuid,
+                list.join("\n")
+            );
+        }
+    };
+    debug!("Resolved {} to {} at {}", args.rvuid, rvuid, path);
+
+    let same = |ct: &CompletedTarget| ct.rvuid.uuid == rvuid.uuid && ct.reopened_at.is_none();
+    let mut completed_target = match completed_targets.iter().rposition(same) {
+        Some(idx) => completed_targets[idx].clone(),
+        None => {
+            info!("No completed entry for {} yet, adding one.", rvuid);
+            CompletedTarget::from(Target::parse(path.as_ref())?)
+        }
+    };
+    let any_given = args.hit.is_some()
+        || args.score.is_some()
+        || args.notes.is_some()
+        || args.transcript.is_some();
+    if !any_given {
+        println!("Recording results for {} ({})", rvuid, path);
+        completed_target.interactive_ask_results()?;
+    }
+    if let Some(ref hit) = args.hit {
+        completed_target.hit = parse_hit(hit)?;
+    }
+    if args.score.is_some() {
+        completed_target.score = args.score;
+    }
+    if let Some(ref notes) = args.notes {
+        completed_target.notes = Some(notes.clone()).filter(|n| !n.is_empty());
+    }
+    if let Some(ref transcript) = args.transcript {
+        let text = persist::read_input(transcript)?;
+        completed_target.transcript = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    }
+    info!("Recorded {}", completed_target);
+    CompletedTarget::update(&args.completed, |completed_targets| {
+        match completed_targets.iter().rposition(same) {
+            Some(idx) => completed_targets[idx] = completed_target,
+            None => completed_targets.push(completed_target),
+        }
+        Ok(())
+    })
+}
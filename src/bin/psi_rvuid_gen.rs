@@ -2,8 +2,10 @@ use clap::Parser;
 use log::debug;
 use std::path::Path;
 
+use psitool::bundle::Bundle;
 use psitool::logger;
 use psitool::rvuid::Rvuid;
+use psitool::target::TargetType;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -23,6 +25,12 @@ fn main() -> anyhow::Result<()> {
     logger::init(args.verbose, args.quiet);
     for path in args.paths {
         let path = Path::new(&path);
+        if Bundle::is_bundle_dir(path) || TargetType::parse(path) == Some(TargetType::Bundle) {
+            // Bundles hash their components, not the archive or directory itself.
+            let (_, rvuid) = Bundle::load(path)?;
+            println!("{} = {}", path.display(), rvuid);
+            continue;
+        }
         if path.is_dir() {
             debug!("Skipping {} - it's a directory.", path.display());
             continue;
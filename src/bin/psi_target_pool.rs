@@ -1,12 +1,16 @@
 use clap::{ArgAction, Parser};
-use log::{debug, error, info, warn};
+use log::{debug, info};
 use std::io::{self, Write};
 
 use psitool::cache::{CacheMap, CachedHash};
-use psitool::config::{Config, TargetPool, random_pool};
+use psitool::config::{Config, TargetPool, Viewer, random_pool};
+use psitool::journal::{Event, Journal, Record, new_session_id};
 use psitool::logger;
+use psitool::persist;
+use psitool::preview::PreviewMode;
 use psitool::rvuid::Rvuid;
-use psitool::target::{CompletedTarget, TargetType};
+use psitool::target::{CompletedTarget, Results, parse_hit};
+use psitool::tui;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -30,6 +34,57 @@ struct Args {
     #[arg(short, long, help = "dont open the target after")]
     skip_open: bool,
 
+    #[arg(
+        short = 'P',
+        long,
+        default_value = "auto",
+        help = "how to draw image targets in the terminal when they aren't opened (auto, kitty, sixel, blocks, none)"
+    )]
+    preview: PreviewMode,
+
+    #[arg(
+        short,
+        long,
+        help = "run the session in a full-screen terminal UI with a timer, notes and a scoring form"
+    )]
+    tui: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "tui",
+        help = "record whether it was a hit (y/yes/n/no) instead of prompting"
+    )]
+    hit: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "tui",
+        value_parser = clap::value_parser!(u32).range(0..=100),
+        help = "record a score out of 100 instead of prompting"
+    )]
+    score: Option<u32>,
+
+    #[arg(
+        long,
+        conflicts_with = "tui",
+        help = "record notes instead of prompting"
+    )]
+    notes: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "tui",
+        help = "read the results from a JSON or YAML file instead of prompting (- for stdin), overridden by --hit, --score and --notes"
+    )]
+    results: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["hit", "score", "notes", "results"],
+        help = "reveal the target but don't ask for or record any results"
+    )]
+    no_record: bool,
+
     #[arg(
         short,
         long,
@@ -73,54 +128,85 @@ struct Args {
         help = "the target pools to read from, EXCLUDING this label"
     )]
     exclude_label: Option<String>,
+
+    #[arg(
+        short = 'u',
+        long,
+        conflicts_with = "completed",
+        help = "the viewer profile from the config to use (their completed targets, pools and frontloading)"
+    )]
+    viewer: Option<String>,
+
+    #[arg(
+        long,
+        help = "also skip targets any viewer in the config has already completed (for group sessions)"
+    )]
+    exclude_all_viewers: bool,
+}
+
+/// Fill in the viewer's completed targets and defaults, where they weren't passed explicitly.
+fn apply_viewer(args: &mut Args, viewer: &Viewer) {
+    args.completed = viewer.completed.clone();
+    if args.pools.is_empty() && args.include_label.is_none() && args.exclude_label.is_none() {
+        args.pools = viewer.pools.clone();
+        args.include_label = viewer.include_label.clone();
+        args.exclude_label = viewer.exclude_label.clone();
+    }
+    if args.frontload == 0 {
+        args.frontload = viewer.frontload.unwrap_or(0);
+    }
+}
+
+/// The results given on the command line, if any, so the session runs without prompting.
+fn given_results(args: &Args) -> anyhow::Result<Option<Results>> {
+    let mut results = match args.results.as_deref() {
+        Some(input) => Some(Results::parse(&persist::read_input(input)?)?),
+        None => None,
+    };
+    if let Some(ref hit) = args.hit {
+        results.get_or_insert_default().hit = parse_hit(hit)?;
+    }
+    if let Some(score) = args.score {
+        results.get_or_insert_default().score = Some(score);
+    }
+    if let Some(ref notes) = args.notes {
+        results.get_or_insert_default().notes = Some(notes.clone());
+    }
+    Ok(results)
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
     logger::init(args.verbose, args.quiet);
+    let results = given_results(&args)?;
     let cfg = Config::load(&args.config)?;
-    let mut completed_targets: Vec<CompletedTarget> = CompletedTarget::parse(&args.completed)?;
-    let mut cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
-    let mut tpools: Vec<&TargetPool> = Vec::new();
-    for pool in args.pools.clone() {
-        if !cfg.has_pool(&pool) {
-            warn!("cant find passed pool '{}'", pool);
-            anyhow::bail!("couldnt find pool '{}'", pool);
-        }
+    if let Some(name) = args.viewer.clone() {
+        apply_viewer(&mut args, cfg.get_viewer(&name)?);
+        info!(
+            "Viewing as {}, completed targets in {}",
+            name, args.completed
+        );
     }
-    for pool in cfg.list_pools() {
-        let tpool = cfg.get_pool(&pool).unwrap();
-        if let Some(ref exclude) = args.exclude_label
-            && tpool.labels.contains(exclude)
-        {
-            debug!(
-                "excluding '{}' pool due to exclude label '{}'",
-                pool, exclude
-            );
-        } else if args.pools.contains(&pool) {
-            debug!("including pool '{}' by name", pool);
-            tpools.push(tpool);
-        } else if let Some(ref include) = args.include_label
-            && tpool.labels.contains(include)
-        {
-            debug!("including pool '{}' by label {}", pool, include);
-            tpools.push(tpool);
-        } else if args.pools.is_empty()
-            && args.include_label.is_none()
-            && args.exclude_label.is_none()
-        {
-            debug!(
-                "including pool '{}' because no options passed (all pools)",
-                pool
-            );
-            tpools.push(tpool);
-        }
-    }
-    let completed_rvuids: Vec<Rvuid> = if args.reuse_targets {
+    let completed_targets: Vec<CompletedTarget> = CompletedTarget::parse(&args.completed)?;
+    let mut cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
+    let tpools: Vec<&TargetPool> = cfg.select_pools(
+        &args.pools,
+        args.include_label.as_deref(),
+        args.exclude_label.as_deref(),
+    )?;
+    let mut completed_rvuids: Vec<Rvuid> = if args.reuse_targets {
         Vec::new()
     } else {
-        completed_targets.iter().map(|t| t.rvuid.clone()).collect()
+        CompletedTarget::completed_rvuids(&completed_targets)
     };
+    if args.exclude_all_viewers {
+        for name in cfg.list_viewers() {
+            let viewer = cfg.get_viewer(&name)?;
+            let theirs = CompletedTarget::parse(&viewer.completed)?;
+            debug!("excluding {} completed targets of {}", theirs.len(), name);
+            completed_rvuids.extend(CompletedTarget::completed_rvuids(&theirs));
+        }
+    }
     debug!("found {} target pools to pull target from", tpools.len());
     let mut total = 0usize;
     for tpool in tpools.clone() {
@@ -133,6 +219,48 @@ fn main() -> anyhow::Result<()> {
     let tpool = random_pool(&tpools, &completed_rvuids, &mut cachemap)?;
     let target = tpool.random_target(&completed_rvuids, &mut cachemap)?;
     debug!("Chose rvuid {}", target.rvuid);
+    // With a journal, the selection and reveal are logged as they happen, not just the results.
+    let journal = Journal::is_journal(&args.completed).then(|| Journal::open(&args.completed));
+    let session = new_session_id();
+    if let Some(ref journal) = journal {
+        journal.append_event(
+            &session,
+            Event::Selected {
+                rvuid: target.rvuid.clone(),
+                path: target.path.display().to_string(),
+                meta_path: target.meta_path.as_ref().map(|p| p.display().to_string()),
+                group: None,
+            },
+        )?;
+    }
+    if args.tui {
+        let outcome = tui::run(&target, args.frontload, args.skip_open, !args.no_record)?;
+        if let Some(ref journal) = journal
+            && let Some(at) = outcome.revealed_at
+        {
+            journal.append(&[Record {
+                at,
+                session: session.clone(),
+                event: Event::Revealed,
+            }])?;
+        }
+        match outcome.completed {
+            Some(mut completed_target) => {
+                if journal.is_some() {
+                    completed_target.session = Some(session);
+                }
+                debug!("Adding completed target {}", completed_target);
+                CompletedTarget::update(&args.completed, |completed_targets| {
+                    completed_targets.push(completed_target);
+                    Ok(())
+                })?;
+            }
+            None if args.no_record => info!("Not recording {}.", target.rvuid),
+            None => info!("Quit without recording {}.", target.rvuid),
+        }
+        CachedHash::dump(&cachemap, &args.cached_hashes)?;
+        return Ok(());
+    }
     println!("Target: {}", target.rvuid);
     if args.frontload > 0 {
         let range_end: usize = target.frontloading.len().min(args.frontload as usize);
@@ -140,39 +268,34 @@ fn main() -> anyhow::Result<()> {
         println!("Frontloading: {:?}", frontloading);
     }
     println!("Remote viewer, begin viewing.");
-    println!("Press ENTER when complete.");
-    io::stdout().flush()?;
-    let mut buf = String::new();
-    io::stdin().read_line(&mut buf)?;
-    println!("Path: {}", target.path.display());
-    if target.target_type == TargetType::Text {
-        match std::fs::read_to_string(&target.path) {
-            Ok(contents) => println!("Target Text:\n{}", contents),
-            _ => {
-                if args.skip_open {
-                    error!("Failed to read target text. You may need to open it manually.");
-                } else {
-                    open::that(&target.path)?;
-                }
-            }
-        }
-    } else if !args.skip_open {
-        // We don't open text files if we can read them above as text.
-        open::that(&target.path)?;
+    if results.is_none() {
+        println!("Press ENTER when complete.");
+        io::stdout().flush()?;
+        let mut buf = String::new();
+        io::stdin().read_line(&mut buf)?;
     }
-    if let Some(ref meta_path) = target.meta_path {
-        println!("YAML meta: {}", meta_path.display());
+    let _components = target.reveal(args.skip_open, args.preview)?;
+    if let Some(ref journal) = journal {
+        journal.append_event(&session, Event::Revealed)?;
     }
-    if !target.meta.is_empty() {
-        for (key, val) in target.iter_meta() {
-            println!("{}: {}", key, val);
-        }
+    if args.no_record {
+        info!("Not recording {}.", target.rvuid);
+        CachedHash::dump(&cachemap, &args.cached_hashes)?;
+        return Ok(());
     }
     let mut completed_target = CompletedTarget::from(target);
-    completed_target.interactive_ask_results();
+    if journal.is_some() {
+        completed_target.session = Some(session);
+    }
+    match results {
+        Some(results) => completed_target.apply_results(results)?,
+        None => completed_target.interactive_ask_results()?,
+    }
     debug!("Adding completed target {}", completed_target);
-    completed_targets.push(completed_target);
-    CompletedTarget::dump(&completed_targets, &args.completed)?;
+    CompletedTarget::update(&args.completed, |completed_targets| {
+        completed_targets.push(completed_target);
+        Ok(())
+    })?;
     CachedHash::dump(&cachemap, &args.cached_hashes)?;
     Ok(())
 }
@@ -0,0 +1,865 @@
+use clap::Parser;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use psitool::bundle::ComponentPaths;
+use psitool::cache::{CacheMap, CachedHash};
+use psitool::config::{Config, random_pool};
+use psitool::logger;
+use psitool::rich::RichTarget;
+use psitool::rvuid::Rvuid;
+use psitool::target::{CompletedTarget, Target, TargetType};
+
+/// Uploaded sketches bigger than this are refused.
+const MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    #[arg(short, long, help = "verbose logging (debug logs)")]
+    verbose: bool,
+
+    #[arg(short, long, help = "quiet logging (warn+ logs)")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "~/.psitool.yaml",
+        help = "the config with the target pools"
+    )]
+    config: String,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value = "~/.psitool_completed_targets.yaml",
+        help = "the yaml config with a list of completed targets (used to cache what you RV'd already)"
+    )]
+    completed: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_cached_hashes.yaml",
+        help = "the yaml config with a list of cached hashes so it doesn't have to compute them every run"
+    )]
+    cached_hashes: String,
+
+    #[arg(
+        long,
+        default_value = "~/.psitool_sketches",
+        help = "the directory uploaded sketches are saved to"
+    )]
+    sketches: String,
+
+    #[arg(
+        short,
+        long,
+        default_value = "127.0.0.1",
+        help = "the address to listen on (keep it on localhost, there's no authentication)"
+    )]
+    bind: String,
+
+    #[arg(short, long, default_value_t = 8787, help = "the port to listen on")]
+    port: u16,
+}
+
+type HttpResponse = tiny_http::Response<Cursor<Vec<u8>>>;
+
+struct Session {
+    target: Target,
+    frontloading: Vec<String>,
+    transcript: Option<String>,
+    sketch: Option<String>,
+    revealed: bool,
+    /// A bundle's components, extracted at the reveal and deleted with the session.
+    components: ComponentPaths,
+}
+
+struct App {
+    cfg: Config,
+    completed: String,
+    cached_hashes: String,
+    sketches: PathBuf,
+    cachemap: CacheMap,
+    sessions: HashMap<String, Session>,
+    bind: String,
+    port: u16,
+}
+
+struct Part {
+    name: String,
+    data: Vec<u8>,
+}
+
+fn header(field: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn html(body: String) -> HttpResponse {
+    tiny_http::Response::from_string(body)
+        .with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    tiny_http::Response::from_data(Vec::new())
+        .with_status_code(303)
+        .with_header(header("Location", location))
+}
+
+fn error_page(status: u16, message: &str) -> HttpResponse {
+    html(page("Error", &format!("<p>{}</p>", escape(message)))).with_status_code(status)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title} - psitool</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }}
+img, video {{ max-width: 100%; }}
+textarea {{ width: 100%; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td, th {{ border-bottom: 1px solid #ccc; padding: 0.3em; text-align: left; }}
+.rvuid {{ font-family: monospace; font-size: 2em; }}
+</style></head>
+<body><nav><a href="/">New session</a> | <a href="/history">History</a></nav>
+<h1>{title}</h1>
+{body}
+</body></html>"#,
+        title = escape(title),
+        body = body
+    )
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "tif" | "tiff" => "image/tiff",
+        "svg" => "image/svg+xml",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "ogv" => "video/ogg",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "target" | "md" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn serve_file(path: &Path) -> HttpResponse {
+    match fs::read(path) {
+        Ok(bytes) => tiny_http::Response::from_data(bytes)
+            .with_header(header("Content-Type", content_type(path))),
+        Err(e) => {
+            warn!("Failed to read {}: {}", path.display(), e);
+            error_page(404, "file not found")
+        }
+    }
+}
+
+/// An element showing the file at `url`, or its text inline.
+fn embed(url: &str, path: &Path, target_type: &TargetType) -> String {
+    match target_type {
+        TargetType::Text => format!(
+            "<pre>{}</pre>",
+            escape(&fs::read_to_string(path).unwrap_or_default())
+        ),
+        TargetType::Markdown => {
+            let text = fs::read_to_string(path).unwrap_or_default();
+            match RichTarget::parse(&text) {
+                Ok(rich) => rich_fields(&rich),
+                Err(_) => format!("<pre>{}</pre>", escape(&text)),
+            }
+        }
+        TargetType::Video => format!(r#"<video controls src="{}"></video>"#, url),
+        TargetType::Audio => format!(r#"<audio controls src="{}"></audio>"#, url),
+        TargetType::Bundle => String::new(),
+        _ => format!(r#"<img src="{}">"#, url),
+    }
+}
+
+fn rich_fields(rich: &RichTarget) -> String {
+    let mut out = String::from("<dl>");
+    for (key, val) in rich.iter_fields() {
+        out.push_str(&format!("<dt>{}</dt><dd>{}</dd>", key, escape(val)));
+    }
+    out.push_str("</dl>");
+    if !rich.body.is_empty() {
+        out.push_str(&format!("<pre>{}</pre>", escape(&rich.body)));
+    }
+    out
+}
+
+fn parse_urlencoded(body: &str) -> Vec<(String, String)> {
+    body.split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            let decode = |s: &str| {
+                let s = s.replace('+', " ");
+                urlencoding::decode(&s).map(|c| c.into_owned()).unwrap_or(s)
+            };
+            (decode(k), decode(v))
+        })
+        .collect()
+}
+
+fn form_value<'a>(form: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    form.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+        .filter(|v| !v.trim().is_empty())
+}
+
+fn parse_multipart(content_type: &str, body: &[u8]) -> anyhow::Result<Vec<Part>> {
+    let boundary = content_type
+        .split(';')
+        .filter_map(|p| p.trim().strip_prefix("boundary="))
+        .next()
+        .map(|b| b.trim_matches('"'))
+        .ok_or_else(|| anyhow::anyhow!("multipart request without a boundary"))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    for chunk in split_bytes(body, &delimiter).into_iter().skip(1) {
+        if chunk.starts_with(b"--") {
+            break;
+        }
+        let chunk = chunk.strip_prefix(b"\r\n").unwrap_or(chunk);
+        let Some(split) = chunk.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&chunk[..split]);
+        let data = &chunk[split + 4..];
+        let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+        let disposition = headers
+            .lines()
+            .find(|l| l.to_ascii_lowercase().starts_with("content-disposition"))
+            .unwrap_or("");
+        let attr = |key: &str| {
+            disposition.split(';').find_map(|p| {
+                p.trim()
+                    .strip_prefix(key)
+                    .and_then(|v| v.strip_prefix('='))
+                    .map(|v| v.trim_matches('"').to_string())
+            })
+        };
+        if let Some(name) = attr("name") {
+            parts.push(Part {
+                name,
+                data: data.to_vec(),
+            });
+        }
+    }
+    Ok(parts)
+}
+
+fn split_bytes<'a>(haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            out.push(&haystack[start..i]);
+            i += needle.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.push(&haystack[start..]);
+    out
+}
+
+fn read_body(request: &mut tiny_http::Request) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > MAX_UPLOAD_BYTES {
+        anyhow::bail!("request is larger than {} bytes", MAX_UPLOAD_BYTES);
+    }
+    Ok(body)
+}
+
+fn header_value(request: &tiny_http::Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+fn request_content_type(request: &tiny_http::Request) -> String {
+    header_value(request, "Content-Type").unwrap_or_default()
+}
+
+/// A `Host` header's name and port, like `127.0.0.1:8787` or `[::1]:8787`.
+fn split_host(host: &str) -> (&str, Option<&str>) {
+    if let Some(rest) = host.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((name, tail)) => (name, tail.strip_prefix(':')),
+            None => (host, None),
+        };
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    }
+}
+
+/// The extension to save an uploaded sketch with, from its contents. Only raster images are
+/// accepted: an SVG could carry script that would run on this server's origin.
+fn sketch_extension(bytes: &[u8]) -> Option<&'static str> {
+    match TargetType::from_magic(bytes)? {
+        TargetType::Png => Some("png"),
+        TargetType::Jpeg => Some("jpg"),
+        TargetType::Webp => Some("webp"),
+        TargetType::Gif => Some("gif"),
+        _ => None,
+    }
+}
+
+/// Only plain file names, so requests can't escape the directory they're served from.
+fn safe_name(name: &str) -> Option<&str> {
+    let ok =
+        !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\');
+    ok.then_some(name)
+}
+
+impl App {
+    fn index(&self) -> HttpResponse {
+        let mut pools = self.cfg.list_pools();
+        pools.sort();
+        let mut body = String::from(
+            r#"<form method="post" action="/session"><p>Pick the pools to draw a target from (none for all):</p><ul>"#,
+        );
+        for name in pools {
+            let labels = self
+                .cfg
+                .get_pool(&name)
+                .map(|p| p.labels.join(", "))
+                .unwrap_or_default();
+            body.push_str(&format!(
+                r#"<li><label><input type="checkbox" name="pool" value="{0}"> {0}</label> <small>{1}</small></li>"#,
+                escape(&name),
+                escape(&labels)
+            ));
+        }
+        body.push_str(
+            r#"</ul><p><label>Frontloading <select name="frontload">
+<option value="0">none</option><option value="1">1 level</option>
+<option value="2">2 levels</option><option value="3">3 levels</option></select></label></p>
+<p><button type="submit">Start session</button></p></form>"#,
+        );
+        html(page("New session", &body))
+    }
+
+    fn start_session(&mut self, form: &[(String, String)]) -> anyhow::Result<HttpResponse> {
+        let pools: Vec<String> = form
+            .iter()
+            .filter(|(k, _)| k == "pool")
+            .map(|(_, v)| v.clone())
+            .collect();
+        let frontload: usize = form_value(form, "frontload")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let tpools = self.cfg.select_pools(&pools, None, None)?;
+        let completed_rvuids: Vec<Rvuid> =
+            CompletedTarget::completed_rvuids(&CompletedTarget::parse(&self.completed)?);
+        let tpool = random_pool(&tpools, &completed_rvuids, &mut self.cachemap)?;
+        let target = tpool.random_target(&completed_rvuids, &mut self.cachemap)?;
+        CachedHash::dump(&self.cachemap, &self.cached_hashes)?;
+        debug!("Chose rvuid {}", target.rvuid);
+        let frontloading = target.frontloading[..target.frontloading.len().min(frontload)].to_vec();
+        let id = format!("{:016x}", rand::random::<u64>());
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                target,
+                frontloading,
+                transcript: None,
+                sketch: None,
+                revealed: false,
+                components: ComponentPaths::default(),
+            },
+        );
+        Ok(redirect(&format!("/session/{}", id)))
+    }
+
+    fn session(&self, id: &str, session: &Session) -> HttpResponse {
+        if session.revealed {
+            return redirect(&format!("/session/{}/reveal", id));
+        }
+        let mut body = format!(
+            r#"<p>Target:</p><p class="rvuid">{}</p>"#,
+            session.target.rvuid
+        );
+        if !session.frontloading.is_empty() {
+            body.push_str(&format!(
+                "<p>Frontloading: {}</p>",
+                escape(&session.frontloading.join(", "))
+            ));
+        }
+        body.push_str(&format!(
+            r#"<p>Remote viewer, begin viewing.</p>
+<form method="post" action="/session/{}/submit" enctype="multipart/form-data">
+<p><label>Transcript<br><textarea name="transcript" rows="15"></textarea></label></p>
+<p><label>Sketch <input type="file" name="sketch" accept="image/*"></label></p>
+<p><button type="submit">Submit and reveal the target</button></p>
+</form>"#,
+            id
+        ));
+        html(page("Session", &body))
+    }
+
+    fn submit(&mut self, id: &str, request: &mut tiny_http::Request) -> anyhow::Result<()> {
+        let content_type = request_content_type(request);
+        let body = read_body(request)?;
+        let parts = parse_multipart(&content_type, &body)?;
+        let session = self
+            .sessions
+            .get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("no session {}", id))?;
+        for part in parts {
+            match part.name.as_str() {
+                "transcript" => {
+                    let text = String::from_utf8_lossy(&part.data).trim().to_string();
+                    session.transcript = (!text.is_empty()).then_some(text);
+                }
+                "sketch" if !part.data.is_empty() => {
+                    // The extension comes from what the file really is, never the client's name
+                    // for it, so nothing but a plain image is ever served back.
+                    let Some(ext) = sketch_extension(&part.data) else {
+                        anyhow::bail!("a sketch has to be a PNG, JPEG, WebP or GIF image");
+                    };
+                    fs::create_dir_all(&self.sketches)?;
+                    let name = format!(
+                        "{}_{}.{}",
+                        session.target.rvuid,
+                        chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+                        ext
+                    );
+                    let path = self.sketches.join(&name);
+                    fs::write(&path, &part.data)?;
+                    info!("Saved sketch to {}", path.display());
+                    session.sketch = Some(path.display().to_string());
+                }
+                _ => {}
+            }
+        }
+        if !session.revealed
+            && let Some(ref bundle) = session.target.bundle
+        {
+            session.components = bundle.component_paths()?;
+        }
+        session.revealed = true;
+        Ok(())
+    }
+
+    fn reveal(&self, id: &str, session: &Session) -> anyhow::Result<HttpResponse> {
+        if !session.revealed {
+            return Ok(redirect(&format!("/session/{}", id)));
+        }
+        let target = &session.target;
+        let mut body = format!(
+            r#"<p class="rvuid">{}</p><p>{}</p>"#,
+            target.rvuid,
+            escape(&target.path.display().to_string())
+        );
+        let file_url = format!("/session/{}/file", id);
+        body.push_str(&embed(&file_url, &target.path, &target.target_type));
+        if let Some(ref rich) = target.rich
+            && let Some(feedback) = rich.feedback_path(&target.path)
+            && feedback.exists()
+        {
+            body.push_str(&format!(r#"<img src="/session/{}/feedback">"#, id));
+        }
+        if let Some(ref bundle) = target.bundle {
+            if let Some(ref title) = bundle.title {
+                body.push_str(&format!("<h2>{}</h2>", escape(title)));
+            }
+            let paths = &session.components.paths;
+            for (i, (component, path)) in bundle.components.iter().zip(paths).enumerate() {
+                body.push_str(&format!("<h3>{}/{}</h3>", i + 1, bundle.components.len()));
+                if let Some(ref caption) = component.caption {
+                    body.push_str(&format!("<p>{}</p>", escape(caption)));
+                }
+                let url = format!("/session/{}/file/{}", id, i);
+                body.push_str(&embed(&url, path, &component.target_type));
+            }
+        }
+        body.push_str(&meta_table(target));
+        body.push_str(&transcript_html(
+            session.transcript.as_deref(),
+            session.sketch.as_deref(),
+        ));
+        body.push_str(&format!(
+            r#"<form method="post" action="/session/{}/score">
+<p>Was it a hit?
+<label><input type="radio" name="hit" value="yes"> yes</label>
+<label><input type="radio" name="hit" value="no"> no</label>
+<label><input type="radio" name="hit" value="" checked> don't record</label></p>
+<p><label>Score out of 100 <input type="number" name="score" min="0" max="100"></label></p>
+<p><label>Notes<br><textarea name="notes" rows="4"></textarea></label></p>
+<p><button type="submit">Record results</button></p>
+</form>"#,
+            id
+        ));
+        Ok(html(page("Reveal", &body)))
+    }
+
+    fn session_file(&self, session: &Session, rest: &str) -> anyhow::Result<HttpResponse> {
+        if !session.revealed {
+            return Ok(error_page(403, "the target hasn't been revealed yet"));
+        }
+        let target = &session.target;
+        let path = match rest {
+            "file" => target.path.clone(),
+            "feedback" => target
+                .rich
+                .as_ref()
+                .and_then(|r| r.feedback_path(&target.path))
+                .ok_or_else(|| anyhow::anyhow!("target has no feedback"))?,
+            _ => {
+                let idx: usize = rest
+                    .strip_prefix("file/")
+                    .and_then(|i| i.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("no such file"))?;
+                if target.bundle.is_none() {
+                    anyhow::bail!("target isn't a bundle");
+                }
+                session
+                    .components
+                    .paths
+                    .get(idx)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("no such bundle item"))?
+            }
+        };
+        Ok(serve_file(&path))
+    }
+
+    fn score(&mut self, id: &str, form: &[(String, String)]) -> anyhow::Result<HttpResponse> {
+        let session = self
+            .sessions
+            .remove(id)
+            .ok_or_else(|| anyhow::anyhow!("no session {}", id))?;
+        let mut completed_target = CompletedTarget::from(session.target);
+        completed_target.hit = match form_value(form, "hit") {
+            Some("yes") => Some(true),
+            Some("no") => Some(false),
+            _ => None,
+        };
+        completed_target.score = form_value(form, "score")
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .filter(|n| (0..=100).contains(n));
+        completed_target.notes = form_value(form, "notes").map(|s| s.trim().to_string());
+        completed_target.transcript = session.transcript;
+        completed_target.sketch = session.sketch;
+        debug!("Adding completed target {}", completed_target);
+        let total = CompletedTarget::update(&self.completed, |completed_targets| {
+            completed_targets.push(completed_target);
+            Ok(completed_targets.len())
+        })?;
+        info!(
+            "Succesfully wrote {} completed targets to {}",
+            total, self.completed
+        );
+        Ok(redirect(&format!("/history/{}", total - 1)))
+    }
+
+    fn history(&self) -> anyhow::Result<HttpResponse> {
+        let completed_targets = CompletedTarget::parse(&self.completed)?;
+        let mut body = String::from(
+            "<table><tr><th>When</th><th>RVUID</th><th>Target</th><th>Hit</th><th>Score</th><th>Notes</th></tr>",
+        );
+        for (idx, ct) in completed_targets.iter().enumerate().rev() {
+            let name = Path::new(&ct.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            body.push_str(&format!(
+                r#"<tr><td>{}</td><td><a href="/history/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+                ct.completed_at
+                    .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default(),
+                idx,
+                ct.rvuid,
+                escape(&name),
+                ct.hit.map(|h| if h { "yes" } else { "no" }).unwrap_or(""),
+                ct.score.map(|s| s.to_string()).unwrap_or_default(),
+                escape(ct.notes.as_deref().unwrap_or("")),
+            ));
+        }
+        body.push_str("</table>");
+        Ok(html(page("History", &body)))
+    }
+
+    fn history_entry(&self, rest: &str) -> anyhow::Result<HttpResponse> {
+        let completed_targets = CompletedTarget::parse(&self.completed)?;
+        let (idx, file) = match rest.split_once('/') {
+            Some((idx, file)) => (idx, Some(file)),
+            None => (rest, None),
+        };
+        let Some(ct) = idx
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| completed_targets.get(i))
+        else {
+            return Ok(error_page(404, "no such session"));
+        };
+        let path = PathBuf::from(&ct.path);
+        if file == Some("file") {
+            return Ok(serve_file(&path));
+        }
+        let mut body = format!(
+            r#"<p class="rvuid">{}</p><p>{}</p>"#,
+            ct.rvuid,
+            escape(&ct.path)
+        );
+        if let Some(target_type) = TargetType::parse(&path)
+            && path.is_file()
+        {
+            body.push_str(&embed(
+                &format!("/history/{}/file", idx),
+                &path,
+                &target_type,
+            ));
+        }
+        body.push_str("<table>");
+        let rows = [
+            (
+                "When",
+                ct.completed_at.map(|d| d.to_rfc3339()).unwrap_or_default(),
+            ),
+            (
+                "Hit",
+                ct.hit
+                    .map(|h| if h { "yes" } else { "no" }.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("Score", ct.score.map(|s| s.to_string()).unwrap_or_default()),
+            ("Notes", ct.notes.clone().unwrap_or_default()),
+        ];
+        for (key, val) in rows {
+            body.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                key,
+                escape(&val)
+            ));
+        }
+        body.push_str("</table>");
+        body.push_str(&transcript_html(
+            ct.transcript.as_deref(),
+            ct.sketch.as_deref(),
+        ));
+        Ok(html(page("Session", &body)))
+    }
+
+    fn sketch(&self, name: &str) -> HttpResponse {
+        let Some(name) = safe_name(name) else {
+            return error_page(404, "no such sketch");
+        };
+        let path = self.sketches.join(name);
+        // Only images whose contents are really PNG, JPEG, WebP or GIF, whatever they're named.
+        match fs::read(&path)
+            .ok()
+            .filter(|b| sketch_extension(b).is_some())
+        {
+            Some(bytes) => tiny_http::Response::from_data(bytes)
+                .with_header(header("Content-Type", content_type(&path)))
+                .with_header(header("X-Content-Type-Options", "nosniff")),
+            None => error_page(404, "no such sketch"),
+        }
+    }
+
+    /// Whether `host` is this server: the address it's bound to, `localhost` or an IP address, on
+    /// its port. Anything else is a page on another site that's had its name pointed here (DNS
+    /// rebinding), which would otherwise be able to read targets before they're revealed.
+    fn is_own_host(&self, host: &str) -> bool {
+        let (name, port) = split_host(host);
+        let port_ok = port.unwrap_or("80") == self.port.to_string();
+        let name_ok = name.eq_ignore_ascii_case(&self.bind)
+            || name.eq_ignore_ascii_case("localhost")
+            || name.parse::<IpAddr>().is_ok();
+        port_ok && name_ok
+    }
+
+    /// Refuse requests that other sites can make through the viewer's browser: any with a `Host`
+    /// that isn't this server, and POSTs from another origin, which could start sessions or
+    /// record results (cross-site request forgery).
+    fn check_request(&self, request: &tiny_http::Request) -> Result<(), String> {
+        let host = header_value(request, "Host").ok_or("no Host header")?;
+        if !self.is_own_host(&host) {
+            return Err(format!("unexpected Host '{}'", host));
+        }
+        if *request.method() != tiny_http::Method::Post {
+            return Ok(());
+        }
+        let own = format!("http://{}", host);
+        // Browsers send Origin with every POST, and older ones at least the Referer. Other
+        // clients send neither, and can't be used against the viewer anyway.
+        if let Some(origin) = header_value(request, "Origin") {
+            if origin != own {
+                return Err(format!("POST from another origin '{}'", origin));
+            }
+        } else if let Some(referer) = header_value(request, "Referer")
+            && referer != own
+            && !referer.starts_with(&format!("{}/", own))
+        {
+            return Err(format!("POST from another page '{}'", referer));
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, mut request: tiny_http::Request) -> anyhow::Result<()> {
+        let response = match self.check_request(&request) {
+            Err(reason) => {
+                warn!("Refused {} {}: {}", request.method(), request.url(), reason);
+                error_page(403, &reason)
+            }
+            Ok(()) => match self.route(&mut request) {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Failed to handle {}: {:#}", request.url(), e);
+                    error_page(500, &format!("{:#}", e))
+                }
+            },
+        };
+        request.respond(response)?;
+        Ok(())
+    }
+
+    fn route(&mut self, request: &mut tiny_http::Request) -> anyhow::Result<HttpResponse> {
+        let method = request.method().clone();
+        let url = request.url().split('?').next().unwrap_or("/").to_string();
+        debug!("{} {}", method, url);
+        let parts: Vec<&str> = url.trim_matches('/').splitn(3, '/').collect();
+        let response = match (&method, parts.as_slice()) {
+            (tiny_http::Method::Get, [""]) => self.index(),
+            (tiny_http::Method::Post, ["session"]) => {
+                let body = read_body(request)?;
+                let form = parse_urlencoded(&String::from_utf8_lossy(&body));
+                self.start_session(&form)?
+            }
+            (tiny_http::Method::Post, ["session", id, "submit"]) => {
+                let id = id.to_string();
+                self.submit(&id, request)?;
+                redirect(&format!("/session/{}/reveal", id))
+            }
+            (tiny_http::Method::Post, ["session", id, "score"]) => {
+                let id = id.to_string();
+                let body = read_body(request)?;
+                let form = parse_urlencoded(&String::from_utf8_lossy(&body));
+                self.score(&id, &form)?
+            }
+            (tiny_http::Method::Get, ["session", id, rest @ ..]) => match self.sessions.get(*id) {
+                None => error_page(404, "no such session"),
+                Some(session) => match rest {
+                    [] => self.session(id, session),
+                    ["reveal"] => self.reveal(id, session)?,
+                    [rest] => self.session_file(session, rest)?,
+                    _ => error_page(404, "not found"),
+                },
+            },
+            (tiny_http::Method::Get, ["history"]) => self.history()?,
+            (tiny_http::Method::Get, ["history", rest @ ..]) => {
+                self.history_entry(&rest.join("/"))?
+            }
+            (tiny_http::Method::Get, ["sketch", name]) => self.sketch(name),
+            _ => error_page(404, "not found"),
+        };
+        Ok(response)
+    }
+}
+
+fn meta_table(target: &Target) -> String {
+    if target.meta.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<table>");
+    for (key, val) in target.iter_meta() {
+        out.push_str(&format!(
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(&key),
+            escape(&val)
+        ));
+    }
+    out.push_str("</table>");
+    out
+}
+
+fn transcript_html(transcript: Option<&str>, sketch: Option<&str>) -> String {
+    let mut out = String::new();
+    if let Some(transcript) = transcript {
+        out.push_str(&format!(
+            "<h2>Transcript</h2><pre>{}</pre>",
+            escape(transcript)
+        ));
+    }
+    if let Some(name) = sketch
+        .and_then(|s| Path::new(s).file_name())
+        .and_then(|n| n.to_str())
+    {
+        out.push_str(&format!(
+            r#"<h2>Sketch</h2><img src="/sketch/{}">"#,
+            urlencoding::encode(name)
+        ));
+    }
+    out
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    logger::init(args.verbose, args.quiet);
+    let cfg = Config::load(&args.config)?;
+    let cachemap: CacheMap = CachedHash::parse(&args.cached_hashes)?;
+    let sketches = PathBuf::from(shellexpand::tilde(&args.sketches).into_owned());
+    let mut app = App {
+        cfg,
+        completed: args.completed,
+        cached_hashes: args.cached_hashes,
+        sketches,
+        cachemap,
+        sessions: HashMap::new(),
+        bind: args.bind.clone(),
+        port: args.port,
+    };
+    let addr = format!("{}:{}", args.bind, args.port);
+    let server = tiny_http::Server::http(&addr)
+        .map_err(|e| anyhow::anyhow!("failed to listen on {}: {}", addr, e))?;
+    info!("Serving psitool on http://{}/", addr);
+    for request in server.incoming_requests() {
+        if let Err(e) = app.handle(request) {
+            warn!("Failed to respond: {}", e);
+        }
+    }
+    Ok(())
+}
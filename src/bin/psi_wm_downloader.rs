@@ -1,21 +1,24 @@
+use chrono::Utc;
 use clap::Parser;
 use log::{debug, info, warn};
-use once_cell::sync::Lazy;
-use regex::Regex;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fmt;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
-use std::time::Duration;
-
-use psitool::config::Config;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use psitool::config::{Config, Query, TargetPool};
+use psitool::download::{self, DownloadState, RateLimiter, RetryPolicy, Status};
+use psitool::license::{self, License, Policy};
 use psitool::logger;
-use psitool::target::YamlData;
+use psitool::meta::CleanMeta;
+use psitool::persist;
+use psitool::plan::{self, Plan, PlannedFile, SkippedFile};
+use psitool::target::{TargetType, YamlData};
+use psitool::validate;
+use psitool::wiki::{self, Page, WikiClient};
 
-const DEFAULT_QUERY_TIMEOUT_SECS: u64 = 30;
-const DEFAULT_TIMEOUT_SECS: u64 = 180;
+/// How many finished downloads between saves of the download state.
+const SAVE_EVERY: usize = 20;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -43,220 +46,491 @@ struct Args {
     #[arg(short, long, help = "override wiki default_limit")]
     limit: Option<usize>,
 
-    #[arg(help = "the target pool to download for")]
-    pool: String,
-}
-
-static RE_CC_BY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^CC BY(\s+\d+(\.\d+)?)?$").unwrap());
+    #[arg(
+        short,
+        long,
+        default_value_t = 4,
+        help = "how many files to download at once"
+    )]
+    jobs: usize,
 
-fn make_user_agent() -> String {
-    format!(
-        "{}/psi-wm-downloader/{} ({})",
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
-        env!("CARGO_PKG_AUTHORS")
-    )
-}
+    #[arg(
+        long,
+        default_value_t = 2.0,
+        help = "the most requests to send per second, across all downloads (0 for no limit)"
+    )]
+    rate: f64,
 
-const API: &str = "https://commons.wikimedia.org/w/api.php";
+    #[arg(
+        long,
+        default_value_t = 5,
+        help = "how many times to retry a request that failed or was turned away"
+    )]
+    retries: u32,
 
-#[derive(Debug, Deserialize)]
-struct ApiResponse {
-    query: Option<Query>,
-}
+    #[arg(
+        long,
+        help = "ignore the pool's download state and try every search result again"
+    )]
+    fresh: bool,
 
-#[derive(Debug, Deserialize)]
-struct Query {
-    pages: HashMap<String, Page>,
-}
+    #[arg(
+        long,
+        default_value = psitool::meta::DEFAULT_LANGUAGE,
+        help = "the language to keep from metadata the wiki has in several languages"
+    )]
+    lang: String,
 
-#[derive(Debug, Deserialize)]
-struct Page {
-    title: String,
-    imageinfo: Option<Vec<ImageInfo>>,
-}
+    #[arg(
+        long,
+        help = "search and filter as usual, then print what would be downloaded instead of downloading it"
+    )]
+    dry_run: bool,
 
-impl fmt::Display for Page {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Page({})", self.title)
-    }
-}
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "search and filter as usual, then save what would be downloaded to this file instead of downloading it"
+    )]
+    plan: Option<String>,
 
-#[derive(Debug, Deserialize)]
-struct ImageInfo {
-    url: String,
-    extmetadata: Option<HashMap<String, ExtValue>>,
-}
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["dry_run", "plan", "limit", "fresh"],
+        help = "download exactly what a plan saved with --plan lists, without searching again"
+    )]
+    apply_plan: Option<String>,
 
-#[derive(Debug, Deserialize)]
-struct ExtValue {
-    value: serde_json::Value,
+    #[arg(
+        required_unless_present = "apply_plan",
+        help = "the target pool to download for"
+    )]
+    pool: Option<String>,
 }
 
-fn make_client(timeout_secs: Option<u64>) -> anyhow::Result<reqwest::blocking::Client> {
-    let user_agent = make_user_agent();
-    debug!("using user agent: {}", user_agent);
-    let timeout_secs = timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
-    let client = reqwest::blocking::Client::builder()
-        .user_agent(user_agent)
-        .timeout(Duration::from_secs(timeout_secs))
-        .build()?;
-    Ok(client)
+/// What became of one planned download.
+enum Outcome {
+    Saved(String, String),
+    /// Already in the pool, so only its metadata was written.
+    Updated(String),
+    /// Downloaded but not a valid target.
+    Rejected(String),
+    Failed(anyhow::Error),
 }
 
-fn search_images(query: &str, limit: usize) -> anyhow::Result<Vec<Page>> {
-    debug!("searching for query {} with limit {}", query, limit);
-    let client = make_client(Some(DEFAULT_QUERY_TIMEOUT_SECS))?;
-    let params = [
-        ("action", "query"),
-        ("generator", "search"),
-        ("gsrsearch", query),
-        ("gsrlimit", &limit.to_string()),
-        ("gsrnamespace", "6"), // only File: pages
-        ("prop", "imageinfo"),
-        ("iiprop", "url|extmetadata"),
-        ("format", "json"),
-    ];
-    let resp = client.get(API).query(&params).send()?;
-    if !resp.status().is_success() {
-        anyhow::bail!("API error: {}", resp.status());
-    }
-    let resp: ApiResponse = resp.json()?;
-    let pages = resp
-        .query
-        .map(|q| q.pages.into_values().collect())
-        .unwrap_or_default();
-    Ok(pages)
+/// Everything the download workers share.
+struct Downloader {
+    client: reqwest::blocking::Client,
+    limiter: Arc<RateLimiter>,
+    policy: RetryPolicy,
+    out_dir: PathBuf,
+    /// From `TargetPool::license_policies`.
+    policies: Vec<Policy>,
+    language: String,
 }
 
-fn valid_license(license: &str) -> bool {
-    let normalized = license.replace('-', " ").to_uppercase();
-    debug!("Checking license string: '{}'", normalized);
-    normalized == "CC0" || normalized == "PUBLIC DOMAIN" || RE_CC_BY.is_match(&normalized)
-}
+impl Downloader {
+    /// Decide what to do with a search result, without downloading it: what to save it as and
+    /// from where, or why not to.
+    fn plan(&self, query: &Query, page: Page) -> Result<PlannedFile, String> {
+        let Some(info) = page.imageinfo.as_ref().and_then(|v| v.first()) else {
+            return Err("no imageinfo".to_string());
+        };
+
+        let blank = HashMap::new();
+        let meta = info.extmetadata.as_ref().unwrap_or(&blank);
+
+        let license_short = meta
+            .get("LicenseShortName")
+            .and_then(|v| psitool::meta::text(&v.value, &self.language))
+            .unwrap_or_default();
+
+        if !self.policies.is_empty() {
+            let license = License::parse(
+                &license_short,
+                meta.get("AttributionRequired")
+                    .and_then(|v| license::attribution_required(&v.value)),
+            );
+            debug!("Checking license {:?}", license);
+            for policy in &self.policies {
+                policy
+                    .check(&license)
+                    .map_err(|reason| format!("INVALID license: {}", reason))?;
+            }
+            debug!("validated license: {} was {}", page, license.id);
+        }
 
-fn download_and_save(
-    query: &str,
-    page: &Page,
-    out_dir: &str,
-    free_only: bool,
-    frontloading: Vec<String>,
-) -> anyhow::Result<Option<(String, String)>> {
-    debug!("download_and_save {} to {}", page, out_dir);
-    let client = make_client(None)?;
-    let Some(info) = page.imageinfo.as_ref().and_then(|v| v.first()) else {
-        debug!("Couldnt get page.imageinfo of {}", page);
-        return Ok(None);
-    };
+        let rendition = query.filters.choose(info)?;
+
+        let mut file = page.title.trim_start_matches("File:").replace(' ', "_");
+        if rendition.thumbnail {
+            // Thumbnails of some types (TIFF, SVG, PDF) come as JPEG or PNG.
+            let thumb_ext = Path::new(&rendition.url)
+                .extension()
+                .and_then(|e| e.to_str());
+            let ext = Path::new(&file).extension().and_then(|e| e.to_str());
+            if let Some(thumb_ext) = thumb_ext
+                && ext.is_some_and(|ext| !ext.eq_ignore_ascii_case(thumb_ext))
+            {
+                file = Path::new(&file)
+                    .with_extension(thumb_ext)
+                    .display()
+                    .to_string();
+            }
+        }
 
-    let blank = HashMap::new();
-    let meta = info.extmetadata.as_ref().unwrap_or(&blank);
+        if !plan::is_file_name(&file) {
+            return Err(format!("invalid file name '{}'", file));
+        }
+        if TargetType::parse(Path::new(&file)).is_none() {
+            return Err("not a supported target type".to_string());
+        }
 
-    let license_short = meta
-        .get("LicenseShortName")
-        .map(|v| v.value.to_string())
-        .unwrap_or("".to_string())
-        .replace('"', "");
+        Ok(PlannedFile {
+            present: self.out_dir.join(&file).exists(),
+            title: page.title.clone(),
+            query: query.query.clone(),
+            frontloading: query.frontloading.clone(),
+            file,
+            license: license_short,
+            max_bytes: query.filters.max_bytes,
+            rendition,
+            extmetadata: meta
+                .iter()
+                .map(|(k, v)| (k.clone(), v.value.clone()))
+                .collect(),
+        })
+    }
 
-    if free_only {
-        if !valid_license(&license_short) {
-            info!("INVALID license: {} was {}", page, license_short);
-            return Ok(None);
-        } else {
-            info!("validated license: {} was {}", page, license_short);
+    fn download_and_save(&self, planned: &PlannedFile) -> Outcome {
+        match self.try_download_and_save(planned) {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome::Failed(e),
         }
     }
 
-    fs::create_dir_all(out_dir)?;
+    fn try_download_and_save(&self, planned: &PlannedFile) -> anyhow::Result<Outcome> {
+        debug!(
+            "download_and_save {} to {}",
+            planned.title,
+            self.out_dir.display()
+        );
+        let filename = self.out_dir.join(&planned.file);
+        let mut rendition = planned.rendition.clone();
+
+        let present = filename.exists();
+        if present {
+            rendition.bytes = filename.metadata().ok().map(|m| m.len());
+        } else {
+            info!(
+                "Downloading {} from {}: {}",
+                planned.title,
+                rendition.url,
+                filename.display()
+            );
+            let part = download::part_path(&filename);
+            let bytes = download::fetch(
+                &self.client,
+                &self.limiter,
+                &self.policy,
+                &rendition.url,
+                &part,
+            )?;
+            rendition.bytes = Some(bytes.len() as u64);
+            if let Some(max) = planned.max_bytes
+                && bytes.len() as u64 > max
+            {
+                return Ok(Outcome::Rejected(format!(
+                    "size {} is over {}",
+                    bytes.len(),
+                    max
+                )));
+            }
+            let Some(target_type) = TargetType::detect(&filename, &bytes) else {
+                return Ok(Outcome::Rejected(
+                    "contents aren't a supported target type".to_string(),
+                ));
+            };
+            if let Err(e) = validate::validate_bytes(&bytes, &target_type) {
+                return Ok(Outcome::Rejected(format!("invalid download: {}", e)));
+            }
+            persist::write_atomic(&filename, &bytes)?;
+        }
 
-    let title = page.title.trim_start_matches("File:").replace(' ', "_");
-    let filename = format!("{}/{}", out_dir, title);
+        let meta = &planned.extmetadata;
+        let image_description = meta.get("ImageDescription").cloned().unwrap_or_default();
+        let datetime_original = meta.get("DateTimeOriginal").cloned().unwrap_or_default();
+
+        let mut img_metadata = HashMap::new();
+        let mut license_meta = HashMap::new();
+
+        for (k, v) in meta {
+            match k.as_str() {
+                "License"
+                | "LicenseUrl"
+                | "LicenseShortName"
+                | "UsageTerms"
+                | "AttributionRequired"
+                | "Artist"
+                | "Permission"
+                | "Restrictions"
+                | "Copyrighted"
+                | "Credit" => {
+                    license_meta.insert(k.clone(), v.clone());
+                }
+                _ => {
+                    img_metadata.insert(k.clone(), v.clone());
+                }
+            }
+        }
 
-    if !Path::new(&filename).exists() {
-        info!("Downloading {} from {}: {}", page, info.url, filename);
-        let bytes = client.get(&info.url).send()?.bytes()?;
-        let mut file = fs::File::create(&filename)?;
-        file.write_all(&bytes)?;
+        let yaml_data = YamlData {
+            query: planned.query.clone(),
+            frontloading: planned.frontloading.clone(),
+            image_description,
+            datetime_original,
+            img_metadata,
+            license: planned.license.clone(),
+            license_meta,
+            rendition: Some(rendition),
+            cleaned: Some(CleanMeta::new(|key| meta.get(key), &self.language)),
+        };
+
+        let yaml_path = PathBuf::from(format!("{}.yaml", filename.display()));
+        persist::write_atomic(&yaml_path, serde_yaml::to_string(&yaml_data)?.as_bytes())?;
+
+        if present {
+            return Ok(Outcome::Updated(yaml_path.display().to_string()));
+        }
+        Ok(Outcome::Saved(
+            filename.display().to_string(),
+            yaml_path.display().to_string(),
+        ))
     }
+}
 
-    let image_description = meta
-        .get("ImageDescription")
-        .map(|v| v.value.clone())
-        .unwrap_or_default();
-    let datetime_original = meta
-        .get("DateTimeOriginal")
-        .map(|v| v.value.clone())
-        .unwrap_or_default();
-
-    let mut img_metadata = HashMap::new();
-    let mut license_meta = HashMap::new();
-
-    for (k, v) in meta {
-        match k.as_str() {
-            "License"
-            | "LicenseUrl"
-            | "LicenseShortName"
-            | "UsageTerms"
-            | "AttributionRequired"
-            | "Artist"
-            | "Permission"
-            | "Restrictions"
-            | "Copyrighted"
-            | "Credit" => {
-                license_meta.insert(k.clone(), v.value.clone());
+/// Run every query for the pool and plan what to do with each result.
+fn search(
+    args: &Args,
+    pool: &str,
+    tpool: &TargetPool,
+    downloader: &Downloader,
+    state: &DownloadState,
+) -> anyhow::Result<Plan> {
+    let mut plan = Plan {
+        pool: pool.to_string(),
+        created_at: Some(Utc::now()),
+        ..Default::default()
+    };
+    let mut wiki_clients: HashMap<String, WikiClient> = HashMap::new();
+    let mut queued: HashSet<String> = HashSet::new();
+    for query in tpool.iter_queries(args.limit) {
+        info!("query: {}", query);
+        let wiki_client = match wiki_clients.entry(query.endpoint.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                WikiClient::new(
+                    &query.endpoint,
+                    downloader.limiter.clone(),
+                    downloader.policy,
+                )?
+                .with_language(&args.lang),
+            ),
+        };
+        let results = wiki_client.images(&query.source, query.limit, &query.filters)?;
+        let total = results.len();
+        let before = plan.files.len();
+        for page in results {
+            if state.is_finished(&page.title) || !queued.insert(page.title.clone()) {
+                continue;
             }
-            _ => {
-                img_metadata.insert(k.clone(), v.value.clone());
+            let title = page.title.clone();
+            match downloader.plan(&query, page) {
+                Ok(planned) => plan.files.push(planned),
+                Err(reason) => {
+                    info!("Skipping {}: {}", title, reason);
+                    plan.skipped.push(SkippedFile {
+                        title,
+                        query: query.query.clone(),
+                        reason,
+                    });
+                }
             }
         }
+        info!(
+            "found {} results for {}, {} left to download",
+            total,
+            query.query,
+            plan.files.len() - before
+        );
     }
+    Ok(plan)
+}
 
-    let yaml_data = YamlData {
-        query: query.to_string(),
-        frontloading,
-        image_description: image_description.clone(),
-        datetime_original: datetime_original.clone(),
-        img_metadata,
-        license: license_short,
-        license_meta,
-    };
-
-    let yaml_path = format!("{}.yaml", filename);
-    let mut f = fs::File::create(&yaml_path)?;
-    serde_yaml::to_writer(&mut f, &yaml_data)?;
-
-    Ok(Some((filename, yaml_path)))
+fn print_plan(plan: &Plan, each: bool) {
+    if each {
+        for file in &plan.files {
+            let size = file
+                .rendition
+                .bytes
+                .map(plan::format_bytes)
+                .unwrap_or("? B".to_string());
+            let what = if file.present { "PRESENT" } else { "GET" };
+            let license = if file.license.is_empty() {
+                "no license"
+            } else {
+                &file.license
+            };
+            println!(
+                "{} {} -> {} ({}, {}): {}",
+                what, file.title, file.file, size, license, file.rendition.url
+            );
+        }
+        for skipped in &plan.skipped {
+            println!("SKIP {}: {}", skipped.title, skipped.reason);
+        }
+    }
+    let summary = plan.summary();
+    let mut size = format!("~{}", plan::format_bytes(summary.bytes));
+    if summary.unknown_size > 0 {
+        size.push_str(&format!(" plus {} of unknown size", summary.unknown_size));
+    }
+    println!(
+        "{} files to download ({}), {} already present, {} skipped",
+        summary.to_download, size, summary.present, summary.skipped
+    );
+    for (license, count) in &summary.licenses {
+        println!("  {}: {}", license, count);
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     logger::init(args.verbose, args.quiet);
     let cfg = Config::load(&args.config)?;
-    info!("pool chosen was: {}", args.pool);
-    if !cfg.has_pool(&args.pool) {
-        warn!(
-            "cant find pool '{}' of pools: {:?}",
-            args.pool,
-            cfg.list_pools()
+    let applying = args.apply_plan.as_deref().map(Plan::load).transpose()?;
+    let pool = match (&applying, &args.pool) {
+        (Some(plan), Some(pool)) if &plan.pool != pool => {
+            anyhow::bail!("the plan is for pool '{}', not '{}'", plan.pool, pool)
+        }
+        (Some(plan), _) => plan.pool.clone(),
+        (None, Some(pool)) => pool.clone(),
+        (None, None) => anyhow::bail!("no pool to download for"),
+    };
+    info!("pool chosen was: {}", pool);
+    if !cfg.has_pool(&pool) {
+        warn!("cant find pool '{}' of pools: {:?}", pool, cfg.list_pools());
+        anyhow::bail!("pool '{}' not found!'", pool);
+    }
+    let tpool = cfg.get_pool(&pool).unwrap();
+    // Only created once there's something to download into it, not for a dry run or a plan.
+    let out_dir = tpool.dir();
+    let policy = RetryPolicy {
+        retries: args.retries,
+        ..Default::default()
+    };
+    let downloader = Downloader {
+        client: wiki::make_client(None)?,
+        limiter: Arc::new(RateLimiter::new(args.rate)),
+        policy,
+        out_dir: out_dir.clone(),
+        policies: tpool.license_policies(args.free_only),
+        language: args.lang.clone(),
+    };
+    let mut state = if args.fresh {
+        DownloadState::default()
+    } else {
+        DownloadState::load(&out_dir)?
+    };
+    if !state.files.is_empty() {
+        info!(
+            "Resuming: {} downloaded, {} rejected and {} failed in earlier runs",
+            state.count(Status::Done),
+            state.count(Status::Rejected),
+            state.count(Status::Failed)
         );
-        anyhow::bail!("pool '{}' not found!'", args.pool);
     }
-    let tpool = cfg.get_pool(&args.pool).unwrap();
-    for query in tpool.iter_queries(args.limit) {
-        info!("query: {}", query);
-        let dest_dir_buf = tpool.dest_dir()?;
-        let dest_dir = dest_dir_buf.to_str().unwrap();
-        let results = search_images(&query.query, query.limit)?;
-        for page in results {
-            let frontloading = query.frontloading.clone();
-            if let Some((img, meta)) =
-                download_and_save(&query.query, &page, dest_dir, args.free_only, frontloading)?
+
+    let plan = match applying {
+        Some(plan) => {
+            info!(
+                "Applying the plan for {} files from {}",
+                plan.files.len(),
+                args.apply_plan.as_deref().unwrap_or_default()
+            );
+            plan
+        }
+        None => search(&args, &pool, tpool, &downloader, &state)?,
+    };
+    if args.dry_run || args.plan.is_some() {
+        print_plan(&plan, args.dry_run);
+        if let Some(path) = &args.plan {
+            plan.save(path)?;
+            info!(
+                "Saved the plan to {}, run it with --apply-plan {}",
+                path, path
+            );
+        }
+        return Ok(());
+    }
+
+    tpool.dest_dir()?;
+    let (mut saved, mut updated, mut failed, mut finished) = (0, 0, 0, 0);
+    download::run_pool(
+        plan.files,
+        args.jobs,
+        |planned| {
+            let outcome = downloader.download_and_save(&planned);
+            (planned, outcome)
+        },
+        |(planned, outcome)| {
+            let title = &planned.title;
+            match outcome {
+                Outcome::Saved(img, meta) => {
+                    info!("Saved img {} and metadata {}", img, meta);
+                    state.record(title, &planned.query, Status::Done, None);
+                    saved += 1;
+                }
+                Outcome::Updated(meta) => {
+                    info!("Updated metadata {}", meta);
+                    state.record(title, &planned.query, Status::Done, None);
+                    updated += 1;
+                }
+                Outcome::Rejected(reason) => {
+                    warn!("Discarding {}: {}", title, reason);
+                    state.record(title, &planned.query, Status::Rejected, Some(reason));
+                }
+                Outcome::Failed(e) => {
+                    warn!("Failed to download {}: {:#}", title, e);
+                    state.record(
+                        title,
+                        &planned.query,
+                        Status::Failed,
+                        Some(format!("{:#}", e)),
+                    );
+                    failed += 1;
+                }
+            }
+            finished += 1;
+            if finished % SAVE_EVERY == 0
+                && let Err(e) = state.save(&out_dir)
             {
-                info!("Saved img {} and metadata {}", img, meta);
-            } else {
-                warn!("didnt get anything with Page {}", page);
+                warn!("Failed to save the download state: {:#}", e);
             }
-        }
+        },
+    );
+    state.save(&out_dir)?;
+    info!("Downloaded {} new files into {}", saved, out_dir.display());
+    if updated > 0 {
+        info!("Updated the metadata of {} files already there", updated);
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} downloads failed, run it again to retry them (see {})",
+            failed,
+            DownloadState::path(&out_dir).display()
+        );
     }
     Ok(())
 }
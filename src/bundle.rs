@@ -0,0 +1,280 @@
+use anyhow::Context;
+use log::debug;
+use serde::Deserialize;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+use crate::persist;
+use crate::rvuid::Rvuid;
+use crate::target::TargetType;
+use crate::validate;
+
+/// Every bundle has this manifest at its root listing the components in order.
+pub const MANIFEST: &str = "manifest.yaml";
+
+/// The manifest of a bundle target, like:
+///
+/// ```text
+/// title: Mount Rushmore
+/// items:
+///   - path: photo.jpg
+///   - path: map.png
+///     caption: Black Hills, South Dakota
+///   - path: notes.target
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct Manifest {
+    pub title: Option<String>,
+    pub items: Vec<ManifestItem>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ManifestItem {
+    pub path: String,
+    pub caption: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Component {
+    pub name: String,
+    pub caption: Option<String>,
+    pub target_type: TargetType,
+}
+
+/// A target made of several feedback files, either a directory or a `.bundle.zip` archive with a
+/// manifest at its root.
+#[derive(Clone, Debug)]
+pub struct Bundle {
+    pub root: PathBuf,
+    pub archive: bool,
+    pub title: Option<String>,
+    pub components: Vec<Component>,
+}
+
+impl Bundle {
+    pub fn is_bundle_dir(path: &Path) -> bool {
+        path.is_dir() && path.join(MANIFEST).is_file()
+    }
+
+    /// Load a bundle directory or archive, validating every component. The RVUID is computed
+    /// over the component contents in manifest order, so a directory and the same files zipped
+    /// up get the same RVUID.
+    pub fn load(path: &Path) -> anyhow::Result<(Self, Rvuid)> {
+        if path.is_dir() {
+            let text = fs::read_to_string(path.join(MANIFEST))
+                .with_context(|| format!("no bundle manifest in {}", path.display()))?;
+            let manifest: Manifest = serde_yaml::from_str(&text)?;
+            let mut contents = Vec::new();
+            for item in &manifest.items {
+                let item_path = path.join(checked_name(&item.path)?);
+                let bytes = fs::read(&item_path).with_context(|| {
+                    format!("failed to read bundle item {}", item_path.display())
+                })?;
+                contents.push(bytes);
+            }
+            Self::from_contents(path, false, manifest, contents)
+        } else {
+            let bytes = fs::read(path)
+                .with_context(|| format!("failed to read bytes from {}", path.display()))?;
+            Self::from_archive(path, &bytes)
+        }
+    }
+
+    /// Load a bundle from the bytes of a zip archive.
+    pub fn from_archive(path: &Path, bytes: &[u8]) -> anyhow::Result<(Self, Rvuid)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+            .with_context(|| format!("{} isn't a valid zip archive", path.display()))?;
+        let manifest: Manifest = {
+            let mut entry = archive
+                .by_name(MANIFEST)
+                .with_context(|| format!("no bundle manifest in {}", path.display()))?;
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            serde_yaml::from_str(&text)?
+        };
+        let mut contents = Vec::new();
+        for item in &manifest.items {
+            checked_name(&item.path)?;
+            let mut entry = archive
+                .by_name(&item.path)
+                .with_context(|| format!("bundle item {} missing from archive", item.path))?;
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            contents.push(buf);
+        }
+        Self::from_contents(path, true, manifest, contents)
+    }
+
+    fn from_contents(
+        path: &Path,
+        archive: bool,
+        manifest: Manifest,
+        contents: Vec<Vec<u8>>,
+    ) -> anyhow::Result<(Self, Rvuid)> {
+        if manifest.items.is_empty() {
+            anyhow::bail!("bundle manifest in {} has no items", path.display());
+        }
+        let mut components = Vec::new();
+        let mut hashed: Vec<u8> = Vec::new();
+        for (item, bytes) in manifest.items.into_iter().zip(contents) {
+            let target_type = match TargetType::detect(Path::new(&item.path), &bytes) {
+                Some(TargetType::Bundle) => anyhow::bail!("bundles can't be nested"),
+                Some(t) => t,
+                None => anyhow::bail!("bundle item {} isn't a target type", item.path),
+            };
+            validate::validate_bytes(&bytes, &target_type)
+                .with_context(|| format!("invalid bundle item {}", item.path))?;
+            // Length prefixes so moving bytes between components changes the RVUID.
+            hashed.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            hashed.extend_from_slice(&bytes);
+            components.push(Component {
+                name: item.path,
+                caption: item.caption,
+                target_type,
+            });
+        }
+        debug!(
+            "Loaded bundle {} with {} components",
+            path.display(),
+            components.len()
+        );
+        let bundle = Self {
+            root: path.to_path_buf(),
+            archive,
+            title: manifest.title,
+            components,
+        };
+        Ok((bundle, Rvuid::from_bytes(&hashed)))
+    }
+
+    /// Paths on disk for every component, in manifest order. Archives are extracted into a new
+    /// temporary directory first, which is removed when the result is dropped.
+    pub fn component_paths(&self) -> anyhow::Result<ComponentPaths> {
+        if !self.archive {
+            return Ok(ComponentPaths {
+                paths: self
+                    .components
+                    .iter()
+                    .map(|c| self.root.join(&c.name))
+                    .collect(),
+                _dir: None,
+            });
+        }
+        let dir = tempfile::Builder::new()
+            .prefix("psitool-bundle-")
+            .tempdir()
+            .context("failed to create a directory to extract the bundle")?;
+        let out_dir = dir.path();
+        let bytes = fs::read(&self.root)?;
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut paths = Vec::new();
+        for component in &self.components {
+            let dest = out_dir.join(checked_name(&component.name)?);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut entry = archive.by_name(&component.name)?;
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            fs::write(&dest, buf)?;
+            paths.push(dest);
+        }
+        debug!("Extracted bundle to {}", out_dir.display());
+        Ok(ComponentPaths {
+            paths,
+            _dir: Some(dir),
+        })
+    }
+}
+
+/// Where a bundle's components are on disk. Keep it for as long as the files are needed: an
+/// extracted archive is deleted when this is dropped.
+#[derive(Debug, Default)]
+pub struct ComponentPaths {
+    pub paths: Vec<PathBuf>,
+    /// Held only so the extracted files are removed on drop.
+    _dir: Option<TempDir>,
+}
+
+/// Manifest paths must stay inside the bundle.
+fn checked_name(name: &str) -> anyhow::Result<&Path> {
+    if name == MANIFEST || !persist::is_contained(name) {
+        anyhow::bail!("invalid bundle item path '{}'", name);
+    }
+    Ok(Path::new(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut zw = zip::ZipWriter::new(&mut buf);
+            for (name, bytes) in files {
+                zw.start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                zw.write_all(bytes).unwrap();
+            }
+            zw.finish().unwrap();
+        }
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_bundle_from_archive() {
+        let manifest =
+            b"title: Test\nitems:\n  - path: b.target\n  - path: a.target\n    caption: second\n";
+        let bytes = zip_bytes(&[
+            ("a.target", b"alpha"),
+            ("b.target", b"beta"),
+            (MANIFEST, manifest),
+        ]);
+        let (bundle, rvuid) = Bundle::from_archive(Path::new("t.bundle.zip"), &bytes).unwrap();
+        assert_eq!(bundle.title.as_deref(), Some("Test"));
+        assert_eq!(bundle.components[0].name, "b.target");
+        assert_eq!(bundle.components[1].caption.as_deref(), Some("second"));
+
+        // Manifest order matters for the RVUID.
+        let reordered = b"items:\n  - path: a.target\n  - path: b.target\n";
+        let bytes = zip_bytes(&[
+            ("a.target", b"alpha"),
+            ("b.target", b"beta"),
+            (MANIFEST, reordered),
+        ]);
+        let (_, rvuid2) = Bundle::from_archive(Path::new("t.bundle.zip"), &bytes).unwrap();
+        assert_ne!(rvuid, rvuid2);
+    }
+
+    #[test]
+    fn test_bundle_rejects_bad_items() {
+        let escape = zip_bytes(&[(MANIFEST, b"items:\n  - path: ../secret.target\n")]);
+        assert!(Bundle::from_archive(Path::new("t.bundle.zip"), &escape).is_err());
+        let missing = zip_bytes(&[(MANIFEST, b"items:\n  - path: a.target\n")]);
+        assert!(Bundle::from_archive(Path::new("t.bundle.zip"), &missing).is_err());
+    }
+
+    #[test]
+    fn test_component_paths_are_cleaned_up() {
+        let bytes = zip_bytes(&[
+            ("a.target", b"alpha"),
+            (MANIFEST, b"items:\n  - path: a.target\n"),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.bundle.zip");
+        fs::write(&path, &bytes).unwrap();
+        let (bundle, _) = Bundle::from_archive(&path, &bytes).unwrap();
+        let first = bundle.component_paths().unwrap();
+        let second = bundle.component_paths().unwrap();
+        assert_ne!(first.paths[0], second.paths[0]);
+        assert_eq!(fs::read(&first.paths[0]).unwrap(), b"alpha");
+        let extracted = first.paths[0].clone();
+        drop(first);
+        assert!(!extracted.exists());
+        assert!(second.paths[0].exists());
+    }
+}
@@ -1,36 +1,49 @@
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
+use crate::persist;
 use crate::rvuid::Rvuid;
 use crate::target::Target;
 
 #[derive(Default, Clone, Debug)]
-pub struct CacheMap(HashMap<PathBuf, CachedHash>);
+pub struct CacheMap {
+    hashes: HashMap<PathBuf, CachedHash>,
+    /// Paths removed since loading, so saving doesn't bring them back from the file.
+    removed: HashSet<PathBuf>,
+}
 
 impl CacheMap {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::default()
     }
 
     /// Runs canonicalize on the path and always expects it to work.
     pub fn insert(&mut self, key: PathBuf, value: CachedHash) {
         let canon = std::fs::canonicalize(&key).unwrap();
-        self.0.insert(canon, value);
+        self.removed.remove(&canon);
+        self.hashes.insert(canon, value);
     }
 
     /// Runs canonicalize on the path and always expects it to work.
     pub fn get(&mut self, key: &PathBuf) -> Option<&CachedHash> {
         let canon = std::fs::canonicalize(key).unwrap();
-        self.0.get(&canon)
+        self.hashes.get(&canon)
+    }
+
+    /// Runs canonicalize on the path, so it must still exist.
+    pub fn remove(&mut self, key: &PathBuf) -> Option<CachedHash> {
+        let canon = std::fs::canonicalize(key).ok()?;
+        let removed = self.hashes.remove(&canon);
+        self.removed.insert(canon);
+        removed
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.hashes.len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -43,13 +56,16 @@ impl IntoIterator for CacheMap {
     type IntoIter = std::collections::hash_map::IntoIter<PathBuf, CachedHash>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.hashes.into_iter()
     }
 }
 
 impl FromIterator<(PathBuf, CachedHash)> for CacheMap {
     fn from_iter<I: IntoIterator<Item = (PathBuf, CachedHash)>>(iter: I) -> Self {
-        CacheMap(iter.into_iter().collect::<HashMap<PathBuf, CachedHash>>())
+        CacheMap {
+            hashes: iter.into_iter().collect(),
+            removed: HashSet::new(),
+        }
     }
 }
 
@@ -57,6 +73,10 @@ impl FromIterator<(PathBuf, CachedHash)> for CacheMap {
 pub struct CachedHash {
     pub rvuid: Rvuid,
     pub path: PathBuf,
+    /// Size and modification time (in seconds) of the file when it was parsed and validated, so a
+    /// file changed since then is checked again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stamp: Option<(u64, u64)>,
 }
 
 impl fmt::Display for CachedHash {
@@ -77,12 +97,41 @@ impl From<Target> for CachedHash {
     fn from(target: Target) -> Self {
         Self {
             rvuid: target.rvuid.clone(),
+            stamp: stamp(&target.path),
             path: target.path,
         }
     }
 }
 
+/// The size and modification time of a file, or `None` if it can't be read. For a directory
+/// (a bundle), it's the total size and newest time of everything in it, since editing the
+/// manifest or a component in place doesn't change the directory's own.
+fn stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let modified = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    if !meta.is_dir() {
+        return Some((meta.len(), modified));
+    }
+    std::fs::read_dir(path)
+        .ok()?
+        .try_fold((0, modified), |(size, newest), entry| {
+            let (len, time) = stamp(&entry.ok()?.path())?;
+            Some((size + len, newest.max(time)))
+        })
+}
+
 impl CachedHash {
+    /// Whether the file is unchanged since it was cached. Entries from older caches have no stamp,
+    /// so they're never fresh and get validated again.
+    pub fn is_fresh(&self) -> bool {
+        self.stamp.is_some() && self.stamp == stamp(&self.path)
+    }
+
     /// Given a path to a cache config, parse all cached hashes.
     pub fn parse(path: &str) -> anyhow::Result<CacheMap> {
         let expanded = shellexpand::tilde(path).into_owned();
@@ -100,28 +149,78 @@ impl CachedHash {
         Ok(cachemap)
     }
 
-    /// Save the cached hashes back to a file.
+    /// Save the cached hashes back to a file, merged under its lock with the ones another run
+    /// may have cached since this one loaded it.
     pub fn dump(cachemap: &CacheMap, path: &str) -> anyhow::Result<()> {
-        let cached_hashes: Vec<Self> = cachemap
-            .clone()
-            .into_iter()
-            .map(|(_, ch)| ch.clone())
-            .collect();
         let expanded = shellexpand::tilde(path).into_owned();
         let pbuf = PathBuf::from(&expanded);
         debug!(
             "Writing {} cached hashes to {}",
-            cached_hashes.len(),
+            cachemap.len(),
             pbuf.display()
         );
-        let yaml = serde_yaml::to_string(&cached_hashes)?;
-        let mut file = File::create(pbuf.clone())?;
-        file.write_all(yaml.as_bytes())?;
+        let total = persist::update_yaml(&pbuf, 0, |cached_hashes: &mut Vec<Self>| {
+            cached_hashes.retain(|ch| {
+                !cachemap.hashes.contains_key(&ch.path) && !cachemap.removed.contains(&ch.path)
+            });
+            cached_hashes.extend(cachemap.hashes.values().cloned());
+            Ok(cached_hashes.len())
+        })?;
         debug!(
             "Successfully wrote {} cached hashes to {}",
-            cached_hashes.len(),
+            total,
             pbuf.display()
         );
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_stamp_sees_component_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("rushmore");
+        std::fs::create_dir(&bundle).unwrap();
+        std::fs::write(bundle.join("manifest.yaml"), "items:\n  - path: a.target\n").unwrap();
+        std::fs::write(bundle.join("a.target"), "alpha").unwrap();
+        let before = stamp(&bundle).unwrap();
+        std::fs::write(bundle.join("a.target"), "alpha, edited").unwrap();
+        assert_ne!(stamp(&bundle).unwrap(), before);
+    }
+
+    #[test]
+    fn test_dump_merges_with_other_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache.yaml");
+        let cache = cache.to_str().unwrap();
+        let cached = |name: &str, n: u8| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            let path = path.canonicalize().unwrap();
+            let ch = CachedHash {
+                rvuid: Rvuid::from_bytes(&[n]),
+                path: path.clone(),
+                stamp: None,
+            };
+            (path, ch)
+        };
+        let (a, ch_a) = cached("a", 1);
+        let (b, ch_b) = cached("b", 2);
+        let mut first = CachedHash::parse(cache).unwrap();
+        let mut second = CachedHash::parse(cache).unwrap();
+        first.insert(a.clone(), ch_a);
+        CachedHash::dump(&first, cache).unwrap();
+        second.insert(b.clone(), ch_b);
+        CachedHash::dump(&second, cache).unwrap();
+        assert_eq!(CachedHash::parse(cache).unwrap().len(), 2);
+
+        first.remove(&a);
+        CachedHash::dump(&first, cache).unwrap();
+        let mut merged = CachedHash::parse(cache).unwrap();
+        assert!(merged.get(&a).is_none());
+        assert!(merged.get(&b).is_some());
+    }
+}
@@ -1,18 +1,35 @@
-use log::{error, info};
+use anyhow::Context;
+use log::{debug, error, info, warn};
 use rand::distr::weighted::WeightedIndex;
 use rand::prelude::*;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::cache::{CacheMap, CachedHash};
+use crate::license::Policy;
 use crate::rvuid::Rvuid;
 use crate::target::Target;
+use crate::wiki::{Filters, Source};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     target_pools: HashMap<String, TargetPool>,
+    #[serde(default)]
+    viewers: HashMap<String, Viewer>,
+}
+
+/// Someone sharing the pools, with their own completed targets and defaults for which pools to
+/// pick from and how much to frontload.
+#[derive(Debug, Deserialize)]
+pub struct Viewer {
+    pub completed: String,
+    #[serde(default)]
+    pub pools: Vec<String>,
+    pub include_label: Option<String>,
+    pub exclude_label: Option<String>,
+    pub frontload: Option<u8>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,37 +37,72 @@ pub struct TargetPool {
     pub path: String,
     pub labels: Vec<String>,
     pub wiki: Option<WikiConfig>,
+    /// Which licenses the pool's targets may have, checked when downloading and auditing.
+    pub license: Option<Policy>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct WikiConfig {
     pub default_limit: Option<usize>,
+    /// The MediaWiki API to search, Wikimedia Commons by default.
+    pub endpoint: Option<String>,
+    /// Which files to download, for every query in the pool.
+    #[serde(default)]
+    pub filters: Filters,
     pub queries: Vec<QueryConfig>,
 }
 
+/// One query to fill a pool with. It takes exactly one of `query` (a full-text search),
+/// `category` (with `depth` levels of subcategories) or `titles`.
 #[derive(Debug, Deserialize)]
 pub struct QueryConfig {
-    query: String,
+    query: Option<String>,
+    category: Option<String>,
+    #[serde(default)]
+    depth: u32,
+    titles: Option<Vec<String>>,
     limit: Option<usize>,
     frontloading: Option<Vec<String>>,
+    endpoint: Option<String>,
+    /// Overrides the pool's filters one by one.
+    #[serde(default)]
+    filters: Filters,
 }
 
 pub struct Query {
+    /// What's recorded as the query in each target's metadata.
     pub query: String,
+    pub source: Source,
     pub limit: usize,
     pub frontloading: Vec<String>,
+    pub endpoint: String,
+    pub filters: Filters,
 }
 
 impl std::fmt::Display for Query {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Query({}, {}, {:?})",
-            self.query, self.limit, self.frontloading
+            "Query({}, {}, {:?}, {})",
+            self.source, self.limit, self.frontloading, self.endpoint
         )
     }
 }
 
+impl QueryConfig {
+    fn source(&self) -> anyhow::Result<Source> {
+        match (&self.query, &self.category, &self.titles) {
+            (Some(query), None, None) => Ok(Source::Search(query.clone())),
+            (None, Some(name), None) => Ok(Source::Category {
+                name: name.clone(),
+                depth: self.depth,
+            }),
+            (None, None, Some(titles)) => Ok(Source::Titles(titles.clone())),
+            _ => anyhow::bail!("a wiki query needs exactly one of query, category or titles"),
+        }
+    }
+}
+
 impl Config {
     pub fn load(path: &str) -> anyhow::Result<Self> {
         let expanded = shellexpand::tilde(path).into_owned();
@@ -61,6 +113,12 @@ impl Config {
         }
         let text = fs::read_to_string(pbuf)?;
         let cfg: Config = serde_yaml::from_str(&text)?;
+        for (name, tpool) in &cfg.target_pools {
+            for q in tpool.wiki.iter().flat_map(|w| &w.queries) {
+                q.source()
+                    .with_context(|| format!("bad wiki query in pool '{}'", name))?;
+            }
+        }
         Ok(cfg)
     }
 
@@ -76,6 +134,89 @@ impl Config {
         self.target_pools.keys().cloned().collect()
     }
 
+    pub fn list_viewers(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.viewers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_viewer(&self, viewer: &str) -> anyhow::Result<&Viewer> {
+        match self.viewers.get(viewer) {
+            Some(v) => Ok(v),
+            None => anyhow::bail!(
+                "no viewer '{}' in the config (viewers: {})",
+                viewer,
+                self.list_viewers().join(", ")
+            ),
+        }
+    }
+
+    /// The pools to pick targets from: those named in `pools` or carrying `include_label`, minus
+    /// any carrying `exclude_label`. With no filters at all, every pool is used.
+    pub fn select_pools(
+        &self,
+        pools: &[String],
+        include_label: Option<&str>,
+        exclude_label: Option<&str>,
+    ) -> anyhow::Result<Vec<&TargetPool>> {
+        for pool in pools {
+            if !self.has_pool(pool) {
+                warn!("cant find passed pool '{}'", pool);
+                anyhow::bail!("couldnt find pool '{}'", pool);
+            }
+        }
+        let mut names = self.list_pools();
+        names.sort();
+        let mut tpools: Vec<&TargetPool> = Vec::new();
+        for pool in names {
+            let tpool = self.get_pool(&pool).unwrap();
+            if let Some(exclude) = exclude_label
+                && tpool.has_label(exclude)
+            {
+                debug!(
+                    "excluding '{}' pool due to exclude label '{}'",
+                    pool, exclude
+                );
+            } else if pools.contains(&pool) {
+                debug!("including pool '{}' by name", pool);
+                tpools.push(tpool);
+            } else if let Some(include) = include_label
+                && tpool.has_label(include)
+            {
+                debug!("including pool '{}' by label {}", pool, include);
+                tpools.push(tpool);
+            } else if pools.is_empty() && include_label.is_none() && exclude_label.is_none() {
+                debug!(
+                    "including pool '{}' because no options passed (all pools)",
+                    pool
+                );
+                tpools.push(tpool);
+            }
+        }
+        Ok(tpools)
+    }
+
+    /// Every target in every pool matching `rvuid`, which may be a 40-bit prefix. The same target
+    /// found in several places is only listed once.
+    pub fn find_rvuid(
+        &self,
+        rvuid: &Rvuid,
+        cachemap: &mut CacheMap,
+    ) -> anyhow::Result<Vec<CachedHash>> {
+        let mut names = self.list_pools();
+        names.sort();
+        let mut found: Vec<CachedHash> = Vec::new();
+        for pool in names {
+            let tpool = self.get_pool(&pool).unwrap();
+            for ch in tpool.all_targets(cachemap)? {
+                if ch.rvuid == *rvuid && !found.iter().any(|f| f.rvuid.uuid == ch.rvuid.uuid) {
+                    found.push(ch);
+                }
+            }
+        }
+        Ok(found)
+    }
+
     pub fn iter_queries(&self, pool: &str, default_limit: Option<usize>) -> Vec<Query> {
         if let Some(tpool) = self.get_pool(pool) {
             tpool.iter_queries(default_limit)
@@ -85,6 +226,23 @@ impl Config {
         }
     }
 
+    /// The pool whose directory `path` is in, if any. With pools nested in each other it's the
+    /// innermost one.
+    pub fn pool_for_path(&self, path: &Path) -> Option<(&String, &TargetPool)> {
+        self.target_pools
+            .iter()
+            .map(|(name, tpool)| {
+                (
+                    name,
+                    tpool,
+                    PathBuf::from(shellexpand::tilde(&tpool.path).into_owned()),
+                )
+            })
+            .filter(|(_, _, dir)| path.starts_with(dir))
+            .max_by_key(|(_, _, dir)| dir.components().count())
+            .map(|(name, tpool, _)| (name, tpool))
+    }
+
     pub fn dest_dir(&self, pool: &str) -> anyhow::Result<PathBuf> {
         if let Some(tpool) = self.get_pool(pool) {
             tpool.dest_dir()
@@ -96,6 +254,20 @@ impl Config {
 }
 
 impl TargetPool {
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    /// The license policies a target must meet to be in the pool: its own `license` policy, and
+    /// the free one (`Policy::free`) too with `free_only`. Empty if nothing is checked.
+    pub fn license_policies(&self, free_only: bool) -> Vec<Policy> {
+        free_only
+            .then(Policy::free)
+            .into_iter()
+            .chain(self.license.clone())
+            .collect()
+    }
+
     pub fn iter_queries(&self, default_limit: Option<usize>) -> Vec<Query> {
         let mut out = Vec::new();
 
@@ -108,20 +280,37 @@ impl TargetPool {
                     .unwrap_or(100);
 
                 let frontloading = q.frontloading.clone().unwrap_or(Vec::new());
+                let endpoint = q
+                    .endpoint
+                    .as_deref()
+                    .or(wiki.endpoint.as_deref())
+                    .unwrap_or(crate::wiki::API)
+                    .to_string();
 
+                // Checked when the config was loaded.
+                let Ok(source) = q.source() else {
+                    continue;
+                };
                 out.push(Query {
-                    query: q.query.clone(),
+                    query: source.to_string(),
+                    source,
                     limit,
                     frontloading,
+                    endpoint,
+                    filters: wiki.filters.merged(&q.filters),
                 });
             }
         }
         out
     }
 
+    /// The pool's directory, without creating it like `dest_dir` does.
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(shellexpand::tilde(&self.path).into_owned())
+    }
+
     pub fn dest_dir(&self) -> anyhow::Result<PathBuf> {
-        let expanded = shellexpand::tilde(&self.path).into_owned();
-        let pbuf = PathBuf::from(expanded);
+        let pbuf = self.dir();
         if !pbuf.exists() {
             info!("creating directory: {}", pbuf.display());
             fs::create_dir_all(&pbuf)?;
@@ -176,3 +365,25 @@ pub fn random_pool<'a>(
     let idx = dist.sample(&mut rng);
     Ok(tpools[idx])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_for_nested_path() {
+        let cfg: Config = serde_yaml::from_str(
+            "target_pools:
+  all: {path: /pools, labels: []}
+  lights: {path: /pools/lights, labels: []}
+  lightsabers: {path: /pools/lightsabers, labels: []}
+",
+        )
+        .unwrap();
+        let name = |path: &str| cfg.pool_for_path(Path::new(path)).map(|(n, _)| n.as_str());
+        assert_eq!(name("/pools/lights/a.jpg"), Some("lights"));
+        assert_eq!(name("/pools/lightsabers/a.jpg"), Some("lightsabers"));
+        assert_eq!(name("/pools/b.jpg"), Some("all"));
+        assert_eq!(name("/elsewhere/b.jpg"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::Context;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use zip::write::SimpleFileOptions;
+
+use crate::license::License;
+use crate::meta;
+use crate::target::YamlData;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "text" | "txt" => Ok(Self::Text),
+            other => anyhow::bail!("unknown format '{}' (markdown, html, text)", other),
+        }
+    }
+}
+
+impl Format {
+    /// The name the attribution file gets inside an archive.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Markdown => "ATTRIBUTION.md",
+            Self::Html => "ATTRIBUTION.html",
+            Self::Text => "ATTRIBUTION.txt",
+        }
+    }
+}
+
+/// Who to credit for one target, and under which license.
+#[derive(Clone, Debug, Default)]
+pub struct Credit {
+    /// The target's file name in the pool.
+    pub file: String,
+    pub license: String,
+    pub license_url: Option<String>,
+    pub artist: Option<String>,
+    pub credit: Option<String>,
+    pub attribution_required: bool,
+}
+
+impl Credit {
+    /// The credit for a target from its metadata, or why it can't be passed on.
+    pub fn new(path: &Path, meta: Option<&YamlData>) -> Result<Self, String> {
+        let Some(meta) = meta else {
+            return Err("no metadata, so its license is unknown".to_string());
+        };
+        let license = License::of(meta);
+        if !license.redistributable() {
+            return Err(format!("{} doesn't allow redistribution", license.id));
+        }
+        let cleaned = meta.clean();
+        Ok(Self {
+            file: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            license: meta.license.clone(),
+            license_url: meta
+                .license_meta
+                .get("LicenseUrl")
+                .and_then(|v| meta::text(v, meta::DEFAULT_LANGUAGE)),
+            artist: cleaned.artist,
+            credit: cleaned.credit,
+            attribution_required: license.attribution,
+        })
+    }
+
+    fn author(&self) -> &str {
+        self.artist
+            .as_deref()
+            .or(self.credit.as_deref())
+            .unwrap_or("Unknown author")
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render the attribution file for the targets in a pool.
+pub fn render(pool: &str, credits: &[Credit], format: Format) -> String {
+    let mut out = String::new();
+    let intro = format!(
+        "The {} files in the '{}' target pool are by the authors below, under the licenses listed.",
+        credits.len(),
+        pool
+    );
+    match format {
+        Format::Markdown => {
+            out.push_str(&format!("# Attribution\n\n{}\n\n", intro));
+            for c in credits {
+                let license = match &c.license_url {
+                    Some(url) => format!("[{}]({})", c.license, url),
+                    None => c.license.clone(),
+                };
+                out.push_str(&format!("- `{}` by {}, {}", c.file, c.author(), license));
+                if let Some(credit) = c.credit.as_ref().filter(|_| c.artist.is_some()) {
+                    out.push_str(&format!(" (source: {})", credit));
+                }
+                out.push('\n');
+            }
+        }
+        Format::Html => {
+            out.push_str("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Attribution</title></head>\n<body>\n");
+            out.push_str(&format!(
+                "<h1>Attribution</h1>\n<p>{}</p>\n<ul>\n",
+                escape_html(&intro)
+            ));
+            for c in credits {
+                let license = match &c.license_url {
+                    Some(url) => format!(
+                        "<a href=\"{}\">{}</a>",
+                        escape_html(url),
+                        escape_html(&c.license)
+                    ),
+                    None => escape_html(&c.license),
+                };
+                out.push_str(&format!(
+                    "<li><code>{}</code> by {}, {}",
+                    escape_html(&c.file),
+                    escape_html(c.author()),
+                    license
+                ));
+                if let Some(credit) = c.credit.as_ref().filter(|_| c.artist.is_some()) {
+                    out.push_str(&format!(" (source: {})", escape_html(credit)));
+                }
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ul>\n</body>\n</html>\n");
+        }
+        Format::Text => {
+            out.push_str(&format!("ATTRIBUTION\n\n{}\n\n", intro));
+            for c in credits {
+                out.push_str(&format!("{}\n  Author: {}\n", c.file, c.author()));
+                if let Some(credit) = c.credit.as_ref().filter(|_| c.artist.is_some()) {
+                    out.push_str(&format!("  Source: {}\n", credit));
+                }
+                out.push_str(&format!("  License: {}\n", c.license));
+                if let Some(url) = &c.license_url {
+                    out.push_str(&format!("  License URL: {}\n", url));
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Zip up targets with their metadata, and the attribution file, under a folder named after the
+/// pool.
+pub fn write_archive(
+    out: &Path,
+    pool: &str,
+    targets: &[PathBuf],
+    attribution: (&str, &str),
+) -> anyhow::Result<()> {
+    let file = File::create(out).with_context(|| format!("failed to create {}", out.display()))?;
+    let mut zw = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zw.start_file(format!("{}/{}", pool, attribution.0), options)?;
+    zw.write_all(attribution.1.as_bytes())?;
+    for target in targets {
+        let base = target.parent().unwrap_or(Path::new(""));
+        let mut files = vec![target.clone()];
+        let sidecar = YamlData::sidecar_path(target);
+        if sidecar.is_file() {
+            files.push(sidecar);
+        }
+        while let Some(path) = files.pop() {
+            if path.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    files.push(entry?.path());
+                }
+                continue;
+            }
+            let name = path.strip_prefix(base).unwrap_or(&path);
+            let name = name.to_string_lossy().replace('\\', "/");
+            zw.start_file(format!("{}/{}", pool, name), options)?;
+            zw.write_all(&fs::read(&path)?)?;
+        }
+    }
+    zw.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_credits() {
+        let meta = |license: &str, artist: Option<&str>| YamlData {
+            query: "lighthouse".to_string(),
+            frontloading: Vec::new(),
+            image_description: serde_json::Value::Null,
+            datetime_original: serde_json::Value::Null,
+            img_metadata: HashMap::new(),
+            license: license.to_string(),
+            license_meta: artist
+                .into_iter()
+                .map(|a| ("Artist".to_string(), serde_json::json!(a)))
+                .chain([(
+                    "LicenseUrl".to_string(),
+                    serde_json::json!("https://creativecommons.org/licenses/by/4.0"),
+                )])
+                .collect(),
+            rendition: None,
+            cleaned: None,
+        };
+        let path = Path::new("/pool/Light_house.jpg");
+        let credit = Credit::new(
+            path,
+            Some(&meta(
+                "CC BY 4.0",
+                Some("<a href=\"//commons.wikimedia.org/wiki/User:Jane\">Jane Doe</a>"),
+            )),
+        )
+        .unwrap();
+        assert!(credit.attribution_required);
+        assert!(Credit::new(path, Some(&meta("Fair use", None))).is_err());
+        assert!(Credit::new(path, None).is_err());
+
+        let unknown = Credit::new(path, Some(&meta("CC0", None))).unwrap();
+        let markdown = render("lights", &[credit.clone(), unknown], Format::Markdown);
+        assert!(markdown.contains(
+            "- `Light_house.jpg` by Jane Doe, [CC BY 4.0](https://creativecommons.org/licenses/by/4.0)\n"
+        ));
+        assert!(markdown.contains("by Unknown author, "));
+        let html = render("lights", &[credit], Format::Html);
+        assert!(html.contains("<code>Light_house.jpg</code> by Jane Doe, <a href="));
+    }
+}
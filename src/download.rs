@@ -0,0 +1,421 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{CONTENT_RANGE, RANGE, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::persist;
+
+/// The file in a pool's directory that remembers what's been downloaded into it.
+pub const STATE_FILE: &str = ".psitool_download_state.yaml";
+
+/// Subfolder of a pool where downloads are kept until they're complete, so a half-downloaded
+/// file is never taken for a target.
+pub const PARTIAL_DIR: &str = ".partial";
+
+/// Spaces out requests from every worker so they start no faster than a set rate overall.
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// At most `per_sec` requests a second, or no limit if it's 0.
+    pub fn new(per_sec: f64) -> Self {
+        let interval = if per_sec > 0.0 {
+            Duration::from_secs_f64(1.0 / per_sec)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until it's this caller's turn to send a request.
+    pub fn wait(&self) {
+        let now = Instant::now();
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(now);
+            *next = at + self.interval;
+            at
+        };
+        if at > now {
+            thread::sleep(at - now);
+        }
+    }
+
+    /// Hold every worker back for `delay`, when the server asks us to slow down.
+    pub fn pause(&self, delay: Duration) {
+        let mut next = self.next.lock().unwrap();
+        *next = (*next).max(Instant::now() + delay);
+    }
+}
+
+/// How often and how patiently to retry a request that failed in a way that might not last.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The exponential backoff before retry number `attempt + 1`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max)
+    }
+}
+
+/// The delay a response's `Retry-After` header asks for, in seconds (HTTP dates aren't used by
+/// Wikimedia).
+pub fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Wait out a retry: as long as the server asked for, or else the backoff. Everyone else waits
+/// too if the server asked.
+pub fn back_off(
+    limiter: &RateLimiter,
+    policy: &RetryPolicy,
+    attempt: u32,
+    asked: Option<Duration>,
+) {
+    let delay = match asked {
+        Some(delay) => {
+            limiter.pause(delay);
+            delay
+        }
+        None => policy.delay(attempt),
+    };
+    debug!("retrying in {:?}", delay);
+    thread::sleep(delay);
+}
+
+/// Send a request under the rate limit, retrying connection errors, timeouts, 429s and 5xxs.
+/// Other responses are returned as is, for the caller to check.
+pub fn send(
+    limiter: &RateLimiter,
+    policy: &RetryPolicy,
+    build: impl Fn() -> RequestBuilder,
+) -> anyhow::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        limiter.wait();
+        let (error, asked) = match build().send() {
+            Ok(resp) => {
+                let status = resp.status();
+                if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                    return Ok(resp);
+                }
+                (
+                    anyhow::anyhow!("{} from {}", status, resp.url()),
+                    retry_after(&resp),
+                )
+            }
+            Err(e) if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() => {
+                (anyhow::Error::new(e), None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if attempt >= policy.retries {
+            return Err(error.context(format!("gave up after {} retries", policy.retries)));
+        }
+        warn!("{:#}, retrying", error);
+        back_off(limiter, policy, attempt, asked);
+        attempt += 1;
+    }
+}
+
+/// Download `url` into `part`, picking up from however much of it an earlier, interrupted try
+/// left there, then return the bytes and remove `part`.
+pub fn fetch(
+    client: &Client,
+    limiter: &RateLimiter,
+    policy: &RetryPolicy,
+    url: &str,
+    part: &Path,
+) -> anyhow::Result<Vec<u8>> {
+    if let Some(parent) = part.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut attempt = 0;
+    loop {
+        let have = part.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut resp = send(limiter, policy, || {
+            let req = client.get(url);
+            if have > 0 {
+                req.header(RANGE, format!("bytes={}-", have))
+            } else {
+                req
+            }
+        })?;
+        let status = resp.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && have > 0 {
+            if complete_length(&resp) == Some(have) {
+                debug!("{} was already fully downloaded", url);
+                break;
+            }
+            // The partial file is bad somehow, so start over.
+            fs::remove_file(part)?;
+            continue;
+        }
+        if !status.is_success() {
+            anyhow::bail!("{} from {}", status, url);
+        }
+        let mut file = if status == StatusCode::PARTIAL_CONTENT {
+            debug!("resuming {} from byte {}", url, have);
+            OpenOptions::new().append(true).open(part)?
+        } else {
+            File::create(part)?
+        };
+        match resp.copy_to(&mut file) {
+            Ok(_) => break,
+            Err(e) if attempt < policy.retries => {
+                warn!("download of {} cut off ({}), resuming", url, e);
+                back_off(limiter, policy, attempt, None);
+                attempt += 1;
+            }
+            Err(e) => return Err(e).context(format!("failed to download {}", url)),
+        }
+    }
+    let bytes = fs::read(part)?;
+    fs::remove_file(part)?;
+    Ok(bytes)
+}
+
+/// The full length of the file from an unsatisfiable range response's `Content-Range: bytes */<len>`.
+fn complete_length(resp: &Response) -> Option<u64> {
+    resp.headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes */")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Where a file is downloaded to before it's complete: the pool's `.partial` folder, out of the
+/// way of anything that reads the pool.
+pub fn part_path(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    dir.join(PARTIAL_DIR).join(name)
+}
+
+/// Run `work` on every job with up to `workers` threads, handing each result to `done` on the
+/// calling thread as soon as it's ready.
+pub fn run_pool<J, R>(
+    jobs: Vec<J>,
+    workers: usize,
+    work: impl Fn(J) -> R + Sync,
+    mut done: impl FnMut(R),
+) where
+    J: Send,
+    R: Send,
+{
+    let workers = workers.clamp(1, jobs.len().max(1));
+    let queue = Mutex::new(VecDeque::from(jobs));
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (queue, work) = (&queue, &work);
+            s.spawn(move || {
+                loop {
+                    let Some(job) = queue.lock().unwrap().pop_front() else {
+                        break;
+                    };
+                    if tx.send(work(job)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for result in rx {
+            done(result);
+        }
+    });
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Downloaded and saved.
+    Done,
+    /// Downloaded, but it wasn't a valid target, so there's no point trying again.
+    Rejected,
+    /// Didn't download, to be tried again next run.
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileState {
+    pub status: Status,
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub attempts: u32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What's been downloaded into a pool, by page title, so an interrupted run can carry on.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DownloadState {
+    pub files: BTreeMap<String, FileState>,
+}
+
+impl DownloadState {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(STATE_FILE)
+    }
+
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        persist::read_yaml(&Self::path(dir))
+    }
+
+    pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        persist::write_yaml(&Self::path(dir), self, 0)
+    }
+
+    /// Whether there's nothing left to do for a page.
+    pub fn is_finished(&self, title: &str) -> bool {
+        self.files
+            .get(title)
+            .is_some_and(|f| f.status != Status::Failed)
+    }
+
+    pub fn record(&mut self, title: &str, query: &str, status: Status, reason: Option<String>) {
+        let attempts = self.files.get(title).map(|f| f.attempts).unwrap_or(0) + 1;
+        self.files.insert(
+            title.to_string(),
+            FileState {
+                status,
+                query: query.to_string(),
+                reason,
+                attempts,
+                updated_at: Utc::now(),
+            },
+        );
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.files.values().filter(|f| f.status == status).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch_retries_and_resumes() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.jpg", server.server_addr());
+        let handle = thread::spawn(move || {
+            let mut ranges = Vec::new();
+            for (n, request) in server.incoming_requests().enumerate() {
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .map(|h| h.value.to_string());
+                ranges.push(range.clone());
+                let response = if n == 0 {
+                    tiny_http::Response::from_string("busy")
+                        .with_status_code(503)
+                        .with_header("Retry-After: 0".parse::<tiny_http::Header>().unwrap())
+                } else if range.as_deref() == Some("bytes=5-") {
+                    tiny_http::Response::from_string(" world").with_status_code(206)
+                } else {
+                    tiny_http::Response::from_string("hello world")
+                };
+                request.respond(response).unwrap();
+                if n == 1 {
+                    break;
+                }
+            }
+            ranges
+        });
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let part = part_path(&dir.join("file.jpg"));
+        assert_eq!(part, dir.join(PARTIAL_DIR).join("file.jpg.part"));
+        fs::create_dir_all(part.parent().unwrap()).unwrap();
+        fs::write(&part, "hello").unwrap();
+        let policy = RetryPolicy {
+            retries: 2,
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+        };
+        let bytes = fetch(&Client::new(), &RateLimiter::new(0.0), &policy, &url, &part).unwrap();
+        assert_eq!(bytes, b"hello world");
+        assert!(!part.exists());
+        let ranges = handle.join().unwrap();
+        assert_eq!(ranges, vec![Some("bytes=5-".to_string()); 2]);
+    }
+
+    #[test]
+    fn test_fetch_keeps_a_complete_part() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.jpg", server.server_addr());
+        let handle = thread::spawn(move || {
+            let request = server.incoming_requests().next().unwrap();
+            let response = tiny_http::Response::from_string("")
+                .with_status_code(416)
+                .with_header(
+                    "Content-Range: bytes */5"
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                );
+            request.respond(response).unwrap();
+        });
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let part = part_path(&dir.join("file.jpg"));
+        fs::create_dir_all(part.parent().unwrap()).unwrap();
+        fs::write(&part, "hello").unwrap();
+        let policy = RetryPolicy {
+            retries: 0,
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+        };
+        let bytes = fetch(&Client::new(), &RateLimiter::new(0.0), &policy, &url, &part).unwrap();
+        assert_eq!(bytes, b"hello");
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::target::{CompletedTarget, TargetType, YamlData};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Jsonl,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            other => anyhow::bail!("unknown format '{}' (csv, json, jsonl)", other),
+        }
+    }
+}
+
+/// One completed session joined with its target's metadata and pool. The field names are the
+/// column names, so only ever add to the end.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ExportRow {
+    pub viewer: Option<String>,
+    pub rvuid: String,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub hit: Option<bool>,
+    pub score: Option<u32>,
+    pub notes: Option<String>,
+    pub transcript: Option<String>,
+    pub sketch: Option<String>,
+    pub pool: Option<String>,
+    pub pool_labels: Option<String>,
+    pub path: String,
+    pub target_type: Option<String>,
+    pub meta_path: Option<String>,
+    pub query: Option<String>,
+    pub frontloading: Option<String>,
+    pub license: Option<String>,
+    pub description: Option<String>,
+    pub session: Option<String>,
+    pub group: Option<String>,
+    pub reopened_at: Option<DateTime<Utc>>,
+}
+
+impl ExportRow {
+    pub fn new(viewer: Option<&str>, ct: &CompletedTarget, cfg: &Config) -> Self {
+        let path = PathBuf::from(&ct.path);
+        let pool = cfg.pool_for_path(&path);
+        let meta: Option<YamlData> = ct.meta_path.as_ref().and_then(|p| {
+            let text = fs::read_to_string(p).ok()?;
+            serde_yaml::from_str(&text)
+                .inspect_err(|e| warn!("Failed to parse metadata {}: {}", p, e))
+                .ok()
+        });
+        Self {
+            viewer: viewer.map(String::from),
+            rvuid: ct.rvuid.to_string(),
+            completed_at: ct.completed_at,
+            hit: ct.hit,
+            score: ct.score,
+            notes: ct.notes.clone(),
+            transcript: ct.transcript.clone(),
+            sketch: ct.sketch.clone(),
+            pool: pool.map(|(name, _)| name.clone()),
+            pool_labels: pool
+                .filter(|(_, tpool)| !tpool.labels.is_empty())
+                .map(|(_, tpool)| tpool.labels.join(";")),
+            path: ct.path.clone(),
+            target_type: TargetType::parse(&path).map(|t| format!("{:?}", t).to_lowercase()),
+            meta_path: ct.meta_path.clone(),
+            query: meta.as_ref().map(|m| m.query.clone()),
+            frontloading: meta
+                .as_ref()
+                .filter(|m| !m.frontloading.is_empty())
+                .map(|m| m.frontloading.join(";")),
+            license: meta.as_ref().map(|m| m.license.clone()),
+            description: meta.as_ref().and_then(|m| m.clean().description),
+            session: ct.session.clone(),
+            group: ct.group.clone(),
+            reopened_at: ct.reopened_at,
+        }
+    }
+}
+
+/// Which rows to export. Every filter left as `None` lets everything through.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub pools: Vec<String>,
+    /// `Some(None)` keeps only sessions where it wasn't recorded whether it was a hit.
+    pub hit: Option<Option<bool>>,
+}
+
+impl Filter {
+    pub fn matches(&self, row: &ExportRow) -> bool {
+        let day = row.completed_at.map(|t| t.date_naive());
+        if let Some(since) = self.since
+            && day.is_none_or(|d| d < since)
+        {
+            return false;
+        }
+        if let Some(until) = self.until
+            && day.is_none_or(|d| d > until)
+        {
+            return false;
+        }
+        if !self.pools.is_empty() && !row.pool.as_ref().is_some_and(|p| self.pools.contains(p)) {
+            return false;
+        }
+        self.hit.is_none_or(|hit| row.hit == hit)
+    }
+}
+
+pub fn write(rows: &[ExportRow], format: Format, out: impl Write) -> anyhow::Result<()> {
+    match format {
+        Format::Csv => {
+            if rows.is_empty() {
+                // serde only writes the header along with the first row.
+                let mut out = out;
+                out.write_all(csv_header()?.as_bytes())?;
+                return Ok(());
+            }
+            let mut writer = csv::Writer::from_writer(out);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            let mut out = out;
+            serde_json::to_writer_pretty(&mut out, rows)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => {
+            let mut out = out;
+            for row in rows {
+                serde_json::to_writer(&mut out, row)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The CSV header line, with the column names in order.
+pub fn csv_header() -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(ExportRow::default())?;
+    let text = String::from_utf8(writer.into_inner()?)?;
+    Ok(text.lines().next().unwrap_or_default().to_string() + "\n")
+}
+
+/// The date of a `--since`/`--until` argument.
+pub fn parse_date(s: &str) -> anyhow::Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|e| anyhow::anyhow!("'{}' isn't a YYYY-MM-DD date: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_columns_are_stable() {
+        let mut out = Vec::new();
+        write(&[], Format::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "viewer,rvuid,completed_at,hit,score,notes,transcript,sketch,pool,pool_labels,path,\
+             target_type,meta_path,query,frontloading,license,description,session,group,\
+             reopened_at\n"
+        );
+        let row = ExportRow {
+            rvuid: "R-0000-0000".to_string(),
+            hit: Some(true),
+            score: Some(50),
+            notes: Some("tall, \"white\"".to_string()),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&[row], Format::Csv, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text.lines().nth(1).unwrap(),
+            ",R-0000-0000,,true,50,\"tall, \"\"white\"\"\",,,,,,,,,,,,,,"
+        );
+    }
+}
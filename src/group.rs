@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::persist;
+use crate::rvuid::Rvuid;
+use crate::target::CompletedTarget;
+
+/// Words too common to say anything about a target.
+const STOPWORDS: &str = "a an and are as at be but by feel feels for from got had has have i in is it its \
+    like maybe me my not of on or some something that the there this to very was with";
+
+/// A group session: one target viewed independently by several viewers.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Group {
+    pub id: String,
+    pub rvuid: Rvuid,
+    pub path: PathBuf,
+    pub viewers: Vec<String>,
+    pub frontloading: Vec<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Group {
+    /// Where a group's state is saved. Ids are only letters, digits, `-` and `_`, so one can't
+    /// point outside `dir`.
+    pub fn state_path(dir: &str, id: &str) -> anyhow::Result<PathBuf> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            anyhow::bail!("invalid group session id '{}'", id);
+        }
+        Ok(PathBuf::from(shellexpand::tilde(dir).into_owned()).join(format!("{}.yaml", id)))
+    }
+
+    pub fn load(dir: &str, id: &str) -> anyhow::Result<Self> {
+        let path = Self::state_path(dir, id)?;
+        if !path.exists() {
+            anyhow::bail!("no group session '{}' in {}", id, dir);
+        }
+        persist::read_yaml::<Option<Self>>(&path)?
+            .ok_or_else(|| anyhow::anyhow!("{} is empty", path.display()))
+    }
+
+    /// Save a new group session, refusing to replace one already saved under the same id.
+    pub fn save(&self, dir: &str) -> anyhow::Result<()> {
+        let path = Self::state_path(dir, &self.id)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        persist::update_yaml(&path, 0, |saved: &mut Option<Self>| {
+            if saved.is_some() {
+                anyhow::bail!("group session '{}' already exists in {}", self.id, dir);
+            }
+            *saved = Some(self.clone());
+            Ok(())
+        })
+    }
+}
+
+/// What a group's results have in common.
+#[derive(Debug, Default)]
+pub struct Consensus {
+    /// Descriptors mentioned by more than one viewer, with who mentioned them, most shared first.
+    pub shared: Vec<(String, Vec<String>)>,
+    pub scores: Vec<(String, u32)>,
+}
+
+impl Consensus {
+    /// Build the consensus from each viewer's entry, looking at their transcript and notes.
+    pub fn new(entries: &[(String, &CompletedTarget)]) -> Self {
+        let mut mentions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut scores = Vec::new();
+        for (viewer, ct) in entries {
+            let text = format!(
+                "{} {}",
+                ct.transcript.as_deref().unwrap_or(""),
+                ct.notes.as_deref().unwrap_or("")
+            );
+            for word in descriptors(&text) {
+                mentions.entry(word).or_default().push(viewer.clone());
+            }
+            if let Some(score) = ct.score {
+                scores.push((viewer.clone(), score));
+            }
+        }
+        let mut shared: Vec<(String, Vec<String>)> = mentions
+            .into_iter()
+            .filter(|(_, viewers)| viewers.len() > 1)
+            .collect();
+        shared.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+        Self { shared, scores }
+    }
+
+    /// The lowest, highest and mean score, and their standard deviation.
+    pub fn score_spread(&self) -> Option<(u32, u32, f64, f64)> {
+        if self.scores.is_empty() {
+            return None;
+        }
+        let values: Vec<f64> = self.scores.iter().map(|(_, s)| *s as f64).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        let min = self.scores.iter().map(|(_, s)| *s).min().unwrap();
+        let max = self.scores.iter().map(|(_, s)| *s).max().unwrap();
+        Some((min, max, mean, variance.sqrt()))
+    }
+}
+
+/// The distinct descriptive words in a transcript, lowercased, without stopwords or very short
+/// words.
+pub fn descriptors(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|w| w.trim_matches('\'').to_lowercase())
+        .filter(|w| w.chars().count() > 2 && !STOPWORDS.split_whitespace().any(|s| s == w))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(transcript: &str, score: Option<u32>) -> CompletedTarget {
+        CompletedTarget {
+            score,
+            transcript: Some(transcript.to_string()),
+            group: Some("g".to_string()),
+            ..CompletedTarget::for_test(b"group", "/pool/t.jpg")
+        }
+    }
+
+    #[test]
+    fn test_consensus() {
+        let a = entry("Water, something tall. Cold water!", Some(70));
+        let b = entry("tall structure near water", Some(40));
+        let c = entry("warm, sandy, structure", None);
+        let consensus = Consensus::new(&[
+            ("alice".to_string(), &a),
+            ("bob".to_string(), &b),
+            ("carol".to_string(), &c),
+        ]);
+        assert_eq!(
+            consensus.shared,
+            vec![
+                (
+                    "structure".to_string(),
+                    vec!["bob".to_string(), "carol".to_string()]
+                ),
+                (
+                    "tall".to_string(),
+                    vec!["alice".to_string(), "bob".to_string()]
+                ),
+                (
+                    "water".to_string(),
+                    vec!["alice".to_string(), "bob".to_string()]
+                ),
+            ]
+        );
+        let (min, max, mean, stddev) = consensus.score_spread().unwrap();
+        assert_eq!((min, max), (40, 70));
+        assert_eq!(mean, 55.0);
+        assert_eq!(stddev, 15.0);
+    }
+
+    #[test]
+    fn test_state_path_rejects_bad_ids() {
+        let path = Group::state_path("/groups", "0f3a_b-2").unwrap();
+        assert_eq!(path, PathBuf::from("/groups/0f3a_b-2.yaml"));
+        for id in ["", "../secret", "a/b", ".hidden", "a b"] {
+            assert!(Group::state_path("/groups", id).is_err(), "{}", id);
+        }
+    }
+}
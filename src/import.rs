@@ -0,0 +1,318 @@
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::export::Format;
+use crate::target::{CompletedTarget, parse_hit, parse_score};
+
+/// A row from the file being imported, keyed by column name. Blank cells are left out.
+pub type RawRow = HashMap<String, String>;
+
+/// Which column holds each field. The defaults are the column names `psi-export` writes, so its
+/// output imports as is.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mapping {
+    pub rvuid: String,
+    pub path: String,
+    pub completed_at: String,
+    pub hit: String,
+    pub score: String,
+    pub notes: String,
+    pub transcript: String,
+    /// A chrono format for `completed_at`, for dates the built-in formats don't understand.
+    pub date_format: Option<String>,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        Self {
+            rvuid: "rvuid".to_string(),
+            path: "path".to_string(),
+            completed_at: "completed_at".to_string(),
+            hit: "hit".to_string(),
+            score: "score".to_string(),
+            notes: "notes".to_string(),
+            transcript: "transcript".to_string(),
+            date_format: None,
+        }
+    }
+}
+
+/// One row's fields after mapping, before its target is resolved.
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportRow {
+    pub rvuid: Option<String>,
+    pub path: Option<String>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub hit: Option<bool>,
+    pub score: Option<u32>,
+    pub notes: Option<String>,
+    pub transcript: Option<String>,
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let path = shellexpand::tilde(path).into_owned();
+        let text = fs::read_to_string(&path)?;
+        serde_yaml::from_str(&text).with_context(|| format!("failed to parse mapping {}", path))
+    }
+
+    /// Override one column from a `field=Column` argument.
+    pub fn set(&mut self, spec: &str) -> anyhow::Result<()> {
+        let Some((field, column)) = spec.split_once('=') else {
+            anyhow::bail!("'{}' should look like field=Column", spec);
+        };
+        let column = column.to_string();
+        match field.trim() {
+            "rvuid" => self.rvuid = column,
+            "path" => self.path = column,
+            "completed_at" => self.completed_at = column,
+            "hit" => self.hit = column,
+            "score" => self.score = column,
+            "notes" => self.notes = column,
+            "transcript" => self.transcript = column,
+            "date_format" => self.date_format = Some(column),
+            other => anyhow::bail!(
+                "unknown field '{}' (rvuid, path, completed_at, hit, score, notes, transcript, date_format)",
+                other
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, raw: &RawRow) -> anyhow::Result<ImportRow> {
+        let get = |column: &str| raw.get(column).cloned();
+        Ok(ImportRow {
+            rvuid: get(&self.rvuid),
+            path: get(&self.path),
+            completed_at: get(&self.completed_at)
+                .map(|s| parse_when(&s, self.date_format.as_deref()))
+                .transpose()?,
+            hit: match get(&self.hit) {
+                Some(s) => parse_imported_hit(&s)?,
+                None => None,
+            },
+            score: match get(&self.score) {
+                Some(s) => parse_score(s.trim().trim_end_matches('%'))?,
+                None => None,
+            },
+            notes: get(&self.notes),
+            transcript: get(&self.transcript),
+        })
+    }
+}
+
+/// Read every row of a CSV (with a header), JSON array or JSON Lines file.
+pub fn read_rows(path: &Path, format: Format) -> anyhow::Result<Vec<RawRow>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut rows = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(text.as_bytes());
+            let headers = reader.headers()?.clone();
+            for record in reader.records() {
+                let record = record?;
+                rows.push(
+                    headers
+                        .iter()
+                        .zip(record.iter())
+                        .filter(|(_, value)| !value.trim().is_empty())
+                        .map(|(column, value)| (column.trim().to_string(), value.to_string()))
+                        .collect(),
+                );
+            }
+        }
+        Format::Json => {
+            let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+                serde_json::from_str(&text).context("expected a JSON array of objects")?;
+            rows.extend(objects.into_iter().map(json_row));
+        }
+        Format::Jsonl => {
+            for (n, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let object = serde_json::from_str(line)
+                    .with_context(|| format!("line {} isn't a JSON object", n + 1))?;
+                rows.push(json_row(object));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn json_row(object: serde_json::Map<String, serde_json::Value>) -> RawRow {
+    object
+        .into_iter()
+        .filter_map(|(column, value)| {
+            let value = match value {
+                serde_json::Value::Null => return None,
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            Some((column, value)).filter(|(_, v)| !v.trim().is_empty())
+        })
+        .collect()
+}
+
+/// Parse when a session was done. Dates and times without a timezone are taken as local time.
+pub fn parse_when(s: &str, format: Option<&str>) -> anyhow::Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(when) = DateTime::parse_from_rfc3339(s) {
+        return Ok(when.with_timezone(&Utc));
+    }
+    let formats: Vec<&str> = match format {
+        Some(format) => vec![format],
+        None => vec![
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M",
+        ],
+    };
+    let naive = formats
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| {
+            let date_format = format.unwrap_or("%Y-%m-%d");
+            NaiveDate::parse_from_str(s, date_format)
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        });
+    match naive.and_then(|n| n.and_local_timezone(Local).earliest()) {
+        Some(when) => Ok(when.with_timezone(&Utc)),
+        None => anyhow::bail!("can't make sense of the date '{}'", s),
+    }
+}
+
+/// Like `parse_hit`, but also taking what spreadsheets tend to use.
+fn parse_imported_hit(s: &str) -> anyhow::Result<Option<bool>> {
+    match s.trim().to_lowercase().as_str() {
+        "hit" | "1" | "x" => Ok(Some(true)),
+        "miss" | "0" => Ok(Some(false)),
+        other => parse_hit(other),
+    }
+}
+
+/// What merging an imported entry into the completed targets did.
+#[derive(Debug, PartialEq)]
+pub enum Merged {
+    Added,
+    /// Already there with the same results.
+    Duplicate,
+    /// Already there, but with different results, described as `field: old -> new`.
+    Changed(Vec<String>),
+}
+
+/// Merge `ct` into `current`. It's the same session as an existing entry for the same target
+/// done at the same minute, or, when either doesn't say when, the latest entry for the target
+/// that hasn't been reopened. Differing results are only overwritten with `update`.
+pub fn merge(current: &mut Vec<CompletedTarget>, ct: CompletedTarget, update: bool) -> Merged {
+    let same_minute = |a: DateTime<Utc>, b: DateTime<Utc>| a.timestamp() / 60 == b.timestamp() / 60;
+    let existing = current.iter().rposition(|c| {
+        c.rvuid.uuid == ct.rvuid.uuid
+            && match (c.completed_at, ct.completed_at) {
+                (Some(a), Some(b)) => same_minute(a, b),
+                _ => c.reopened_at.is_none(),
+            }
+    });
+    let Some(idx) = existing else {
+        current.push(ct);
+        return Merged::Added;
+    };
+    let old = &mut current[idx];
+    let mut changes = Vec::new();
+    let show = |v: &Option<String>| v.clone().unwrap_or("-".to_string());
+    if ct.hit.is_some() && ct.hit != old.hit {
+        let fmt = |h: Option<bool>| h.map(|h| if h { "hit" } else { "miss" }).unwrap_or("-");
+        changes.push(format!("hit: {} -> {}", fmt(old.hit), fmt(ct.hit)));
+    }
+    if ct.score.is_some() && ct.score != old.score {
+        let fmt = |s: Option<u32>| s.map(|s| s.to_string()).unwrap_or("-".to_string());
+        changes.push(format!("score: {} -> {}", fmt(old.score), fmt(ct.score)));
+    }
+    if ct.notes.is_some() && ct.notes != old.notes {
+        changes.push(format!(
+            "notes: {:?} -> {:?}",
+            show(&old.notes),
+            show(&ct.notes)
+        ));
+    }
+    if ct.transcript.is_some() && ct.transcript != old.transcript {
+        changes.push("transcript changed".to_string());
+    }
+    if changes.is_empty() {
+        return Merged::Duplicate;
+    }
+    if update {
+        old.hit = ct.hit.or(old.hit);
+        old.score = ct.score.or(old.score);
+        old.notes = ct.notes.or(old.notes.take());
+        old.transcript = ct.transcript.or(old.transcript.take());
+        if old.completed_at.is_none() {
+            old.completed_at = ct.completed_at;
+        }
+    }
+    Merged::Changed(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_and_merge() {
+        let mut mapping = Mapping::default();
+        mapping.set("score=Score (%)").unwrap();
+        mapping.set("hit=Result").unwrap();
+        let raw: RawRow = [
+            ("rvuid", "R-0000-0000"),
+            ("Score (%)", "75%"),
+            ("Result", "Hit"),
+            ("completed_at", "2024-03-01T10:15:30Z"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let row = mapping.apply(&raw).unwrap();
+        assert_eq!(row.score, Some(75));
+        assert_eq!(row.hit, Some(true));
+        assert_eq!(row.path, None);
+        let when = row.completed_at.unwrap();
+        assert_eq!(when.to_rfc3339(), "2024-03-01T10:15:30+00:00");
+
+        let entry = |score: Option<u32>, at: DateTime<Utc>| CompletedTarget {
+            score,
+            completed_at: Some(at),
+            ..CompletedTarget::for_test(b"import", "/pool/t.jpg")
+        };
+        let mut current = Vec::new();
+        assert_eq!(
+            merge(&mut current, entry(Some(50), when), false),
+            Merged::Added
+        );
+        let later = when + chrono::Duration::seconds(20);
+        assert_eq!(
+            merge(&mut current, entry(None, later), false),
+            Merged::Duplicate
+        );
+        assert_eq!(
+            merge(&mut current, entry(Some(60), later), false),
+            Merged::Changed(vec!["score: 50 -> 60".to_string()])
+        );
+        assert_eq!(current[0].score, Some(50));
+        merge(&mut current, entry(Some(60), later), true);
+        assert_eq!(current[0].score, Some(60));
+        let next_day = when + chrono::Duration::days(1);
+        assert_eq!(
+            merge(&mut current, entry(Some(60), next_day), false),
+            Merged::Added
+        );
+        assert_eq!(current.len(), 2);
+    }
+}
@@ -0,0 +1,529 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::persist;
+use crate::rvuid::Rvuid;
+use crate::target::CompletedTarget;
+
+/// Completed targets paths ending in this are an append-only journal rather than a YAML list.
+pub const EXTENSION: &str = "jsonl";
+
+/// Something that happened in a session. Every session starts with `Selected`, and it only shows
+/// up as a completed target once it's `Scored`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Selected {
+        rvuid: Rvuid,
+        path: String,
+        meta_path: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+    Revealed,
+    Scored {
+        hit: Option<bool>,
+        score: Option<u32>,
+        notes: Option<String>,
+        transcript: Option<String>,
+        sketch: Option<String>,
+        completed_at: Option<DateTime<Utc>>,
+    },
+    Edited {
+        hit: Option<bool>,
+        score: Option<u32>,
+        notes: Option<String>,
+        transcript: Option<String>,
+        sketch: Option<String>,
+    },
+    /// Sets or clears when the target was reopened, `null` if it's closed again.
+    Reopened {
+        reopened_at: Option<DateTime<Utc>>,
+    },
+    Removed,
+}
+
+/// One line of the journal: an event, when it was written and the session it belongs to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    pub session: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Record {
+    pub fn new(session: &str, event: Event) -> Self {
+        Self {
+            at: Utc::now(),
+            session: session.to_string(),
+            event,
+        }
+    }
+}
+
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    pub fn is_journal(path: &str) -> bool {
+        Path::new(path).extension().and_then(|e| e.to_str()) == Some(EXTENSION)
+    }
+
+    pub fn open(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(shellexpand::tilde(path).into_owned()),
+        }
+    }
+
+    /// Every record in the journal, in the order they were written. A line that doesn't parse
+    /// (say, the tail of a write that was cut off) is skipped with a warning.
+    pub fn records(&self) -> anyhow::Result<Vec<Record>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let text = fs::read_to_string(&self.path)?;
+        let mut records = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skipping line {} of {}: {}", i + 1, self.path.display(), e),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Add records to the end of the journal.
+    pub fn append(&self, records: &[Record]) -> anyhow::Result<()> {
+        let _lock = persist::lock(&self.path)?;
+        self.append_locked(records)
+    }
+
+    pub fn append_event(&self, session: &str, event: Event) -> anyhow::Result<()> {
+        self.append(&[Record::new(session, event)])
+    }
+
+    /// The completed targets, as the YAML list would have them.
+    pub fn view(&self) -> anyhow::Result<Vec<CompletedTarget>> {
+        Ok(materialize(&self.records()?))
+    }
+
+    /// Change the completed targets through `f` and journal the difference: new entries are
+    /// scored, changed ones edited or reopened, and missing ones removed.
+    pub fn update<R>(
+        &self,
+        f: impl FnOnce(&mut Vec<CompletedTarget>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let _lock = persist::lock(&self.path)?;
+        let records = self.records()?;
+        let before = materialize(&records);
+        let mut after = before.clone();
+        let result = f(&mut after)?;
+        let changes = diff(&records, &before, &mut after);
+        debug!(
+            "Journaling {} events to {}",
+            changes.len(),
+            self.path.display()
+        );
+        self.append_locked(&changes)?;
+        Ok(result)
+    }
+
+    /// Rewrite the journal with one scored event per session holding its latest results, dropping
+    /// edits, removed sessions and sessions left unscored for over a day. The old journal is kept
+    /// as a backup.
+    pub fn compact(&self) -> anyhow::Result<(usize, usize)> {
+        let _lock = persist::lock(&self.path)?;
+        let records = self.records()?;
+        let compacted = compact(&records, Utc::now() - chrono::Duration::days(1));
+        let mut text = String::new();
+        for record in &compacted {
+            text.push_str(&serde_json::to_string(record)?);
+            text.push('\n');
+        }
+        persist::rotate_backups(&self.path, persist::BACKUPS)?;
+        persist::write_atomic(&self.path, text.as_bytes())?;
+        info!(
+            "Compacted {} from {} to {} events",
+            self.path.display(),
+            records.len(),
+            compacted.len()
+        );
+        Ok((records.len(), compacted.len()))
+    }
+
+    /// Journal completed targets from a YAML list, skipping any already in the journal.
+    pub fn import(&self, completed_targets: &[CompletedTarget]) -> anyhow::Result<usize> {
+        self.update(|current| {
+            let mut imported = 0;
+            for ct in completed_targets {
+                let seen = current.iter().any(|c| {
+                    c.rvuid.uuid == ct.rvuid.uuid
+                        && c.completed_at == ct.completed_at
+                        && c.path == ct.path
+                });
+                if !seen {
+                    let mut ct = ct.clone();
+                    ct.session = None;
+                    current.push(ct);
+                    imported += 1;
+                }
+            }
+            Ok(imported)
+        })
+    }
+
+    fn append_locked(&self, records: &[Record]) -> anyhow::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut text = String::new();
+        for record in records {
+            text.push_str(&serde_json::to_string(record)?);
+            text.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+pub fn new_session_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Replay the journal into completed targets, in the order the sessions were scored.
+pub fn materialize(records: &[Record]) -> Vec<CompletedTarget> {
+    let mut selected: HashMap<&str, &Record> = HashMap::new();
+    let mut order: Vec<&str> = Vec::new();
+    let mut done: HashMap<&str, CompletedTarget> = HashMap::new();
+    for record in records {
+        let session = record.session.as_str();
+        match &record.event {
+            Event::Selected { .. } => {
+                selected.insert(session, record);
+            }
+            Event::Revealed => {}
+            Event::Scored {
+                hit,
+                score,
+                notes,
+                transcript,
+                sketch,
+                completed_at,
+            } => {
+                let Some(Record {
+                    event:
+                        Event::Selected {
+                            rvuid,
+                            path,
+                            meta_path,
+                            group,
+                        },
+                    ..
+                }) = selected.get(session)
+                else {
+                    warn!("Session {} was scored but never selected", session);
+                    continue;
+                };
+                if !done.contains_key(session) {
+                    order.push(session);
+                }
+                done.insert(
+                    session,
+                    CompletedTarget {
+                        rvuid: rvuid.clone(),
+                        path: path.clone(),
+                        meta_path: meta_path.clone(),
+                        hit: *hit,
+                        score: *score,
+                        notes: notes.clone(),
+                        transcript: transcript.clone(),
+                        sketch: sketch.clone(),
+                        completed_at: *completed_at,
+                        reopened_at: None,
+                        session: Some(session.to_string()),
+                        group: group.clone(),
+                    },
+                );
+            }
+            Event::Edited {
+                hit,
+                score,
+                notes,
+                transcript,
+                sketch,
+            } => {
+                if let Some(ct) = done.get_mut(session) {
+                    ct.hit = *hit;
+                    ct.score = *score;
+                    ct.notes = notes.clone();
+                    ct.transcript = transcript.clone();
+                    ct.sketch = sketch.clone();
+                }
+            }
+            Event::Reopened { reopened_at } => {
+                if let Some(ct) = done.get_mut(session) {
+                    ct.reopened_at = *reopened_at;
+                }
+            }
+            Event::Removed => {
+                done.remove(session);
+                order.retain(|s| *s != session);
+            }
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|session| done.remove(session))
+        .collect()
+}
+
+/// The events that turn `before` into `after`. Entries in `after` without a session are given one.
+fn diff(
+    records: &[Record],
+    before: &[CompletedTarget],
+    after: &mut [CompletedTarget],
+) -> Vec<Record> {
+    let mut changes = Vec::new();
+    let old: HashMap<&str, &CompletedTarget> = before
+        .iter()
+        .filter_map(|ct| ct.session.as_deref().map(|s| (s, ct)))
+        .collect();
+    let mut kept: HashSet<String> = HashSet::new();
+    for ct in after.iter_mut() {
+        let session = ct.session.get_or_insert_with(new_session_id).clone();
+        kept.insert(session.clone());
+        match old.get(session.as_str()) {
+            Some(prev) => {
+                let results = |c: &CompletedTarget| {
+                    (
+                        c.hit,
+                        c.score,
+                        c.notes.clone(),
+                        c.transcript.clone(),
+                        c.sketch.clone(),
+                    )
+                };
+                if results(prev) != results(ct) {
+                    changes.push(Record::new(
+                        &session,
+                        Event::Edited {
+                            hit: ct.hit,
+                            score: ct.score,
+                            notes: ct.notes.clone(),
+                            transcript: ct.transcript.clone(),
+                            sketch: ct.sketch.clone(),
+                        },
+                    ));
+                }
+                if prev.reopened_at != ct.reopened_at {
+                    changes.push(Record::new(
+                        &session,
+                        Event::Reopened {
+                            reopened_at: ct.reopened_at,
+                        },
+                    ));
+                }
+            }
+            None => changes.extend(new_session(records, &session, ct)),
+        }
+    }
+    for session in old.keys() {
+        if !kept.contains(*session) {
+            changes.push(Record::new(session, Event::Removed));
+        }
+    }
+    changes
+}
+
+/// The events for a session that isn't completed yet, skipping the selection and reveal if
+/// they're already in the journal (e.g. `psi-target-pool` journaled them as they happened).
+fn new_session(records: &[Record], session: &str, ct: &CompletedTarget) -> Vec<Record> {
+    let has = |f: fn(&Event) -> bool| records.iter().any(|r| r.session == session && f(&r.event));
+    let mut events = Vec::new();
+    if !has(|e| matches!(e, Event::Selected { .. })) {
+        events.push(Record::new(
+            session,
+            Event::Selected {
+                rvuid: ct.rvuid.clone(),
+                path: ct.path.clone(),
+                meta_path: ct.meta_path.clone(),
+                group: ct.group.clone(),
+            },
+        ));
+    }
+    if !has(|e| matches!(e, Event::Revealed)) {
+        events.push(Record::new(session, Event::Revealed));
+    }
+    events.push(Record::new(
+        session,
+        Event::Scored {
+            hit: ct.hit,
+            score: ct.score,
+            notes: ct.notes.clone(),
+            transcript: ct.transcript.clone(),
+            sketch: ct.sketch.clone(),
+            completed_at: ct.completed_at,
+        },
+    ));
+    if ct.reopened_at.is_some() {
+        events.push(Record::new(
+            session,
+            Event::Reopened {
+                reopened_at: ct.reopened_at,
+            },
+        ));
+    }
+    events
+}
+
+/// The shortest journal with the same view: edits are folded into the scored event and removed
+/// sessions dropped. Sessions that were selected but never scored are only kept if they were
+/// selected after `since`, as they may still be in progress.
+fn compact(records: &[Record], since: DateTime<Utc>) -> Vec<Record> {
+    let view: HashMap<String, CompletedTarget> = materialize(records)
+        .into_iter()
+        .filter_map(|ct| ct.session.clone().map(|s| (s, ct)))
+        .collect();
+    let removed: HashSet<&str> = records
+        .iter()
+        .filter(|r| r.event == Event::Removed)
+        .map(|r| r.session.as_str())
+        .collect();
+    let pending: HashSet<&str> = records
+        .iter()
+        .filter(|r| matches!(r.event, Event::Selected { .. }) && r.at > since)
+        .map(|r| r.session.as_str())
+        .collect();
+    // Only the last one counts, and it's only needed if the session is still reopened.
+    let last_reopened: HashMap<&str, &Record> = records
+        .iter()
+        .filter(|r| matches!(r.event, Event::Reopened { .. }))
+        .map(|r| (r.session.as_str(), r))
+        .collect();
+    let mut compacted = Vec::new();
+    for record in records {
+        let session = record.session.as_str();
+        match (&record.event, view.get(session)) {
+            (Event::Selected { .. } | Event::Revealed, Some(_)) => compacted.push(record.clone()),
+            (Event::Selected { .. } | Event::Revealed, None)
+                if pending.contains(session) && !removed.contains(session) =>
+            {
+                compacted.push(record.clone())
+            }
+            (Event::Scored { .. }, Some(ct)) => {
+                compacted.push(Record {
+                    at: record.at,
+                    session: session.to_string(),
+                    event: Event::Scored {
+                        hit: ct.hit,
+                        score: ct.score,
+                        notes: ct.notes.clone(),
+                        transcript: ct.transcript.clone(),
+                        sketch: ct.sketch.clone(),
+                        completed_at: ct.completed_at,
+                    },
+                });
+            }
+            (Event::Reopened { .. }, Some(ct))
+                if ct.reopened_at.is_some()
+                    && last_reopened
+                        .get(session)
+                        .is_some_and(|last| std::ptr::eq(*last, record)) =>
+            {
+                compacted.push(record.clone())
+            }
+            _ => {}
+        }
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(n: u8) -> CompletedTarget {
+        CompletedTarget {
+            completed_at: Some(Utc::now()),
+            ..CompletedTarget::for_test(&[n], &format!("/pool/{}.jpg", n))
+        }
+    }
+
+    #[test]
+    fn test_diff_and_materialize() {
+        fn apply(
+            records: &mut Vec<Record>,
+            f: impl Fn(&mut Vec<CompletedTarget>),
+        ) -> Vec<CompletedTarget> {
+            let before = materialize(records);
+            let mut after = before.clone();
+            f(&mut after);
+            let changes = diff(records, &before, &mut after);
+            records.extend(changes);
+            materialize(records)
+        }
+        let mut records: Vec<Record> = Vec::new();
+        apply(&mut records, |v| v.push(entry(1)));
+        let view = apply(&mut records, |v| v.push(entry(2)));
+        assert_eq!(view.len(), 2);
+        let view = apply(&mut records, |v| v[0].score = Some(80));
+        assert_eq!(view[0].score, Some(80));
+        let view = apply(&mut records, |v| v[1].reopened_at = Some(Utc::now()));
+        assert!(view[1].reopened_at.is_some());
+        let view = apply(&mut records, |v| {
+            v.remove(0);
+        });
+        assert_eq!(view.len(), 1);
+        assert_eq!(view[0].path, "/pool/2.jpg");
+
+        // Selected, Revealed and Scored for each, an edit, a reopen and a removal.
+        assert_eq!(records.len(), 9);
+        let compacted = compact(&records, Utc::now());
+        assert_eq!(compacted.len(), 4);
+        assert_eq!(compacted[3].at, records[7].at);
+        let view = materialize(&compacted);
+        assert_eq!(view.len(), 1);
+        assert!(view[0].reopened_at.is_some());
+
+        let view = apply(&mut records, |v| v[0].reopened_at = None);
+        assert!(view[0].reopened_at.is_none());
+        assert_eq!(compact(&records, Utc::now()).len(), 3);
+    }
+
+    #[test]
+    fn test_compact_drops_stale_unscored_sessions() {
+        let selected = |session: &str| {
+            Record::new(
+                session,
+                Event::Selected {
+                    rvuid: Rvuid::from_bytes(&[1]),
+                    path: "/pool/1.jpg".to_string(),
+                    meta_path: None,
+                    group: None,
+                },
+            )
+        };
+        let records = vec![selected("old"), selected("new")];
+        let since = records[0].at;
+        let compacted = compact(&records, since);
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(compacted[0].session, "new");
+    }
+}
@@ -1,5 +1,21 @@
+pub mod bundle;
 pub mod cache;
 pub mod config;
+pub mod credits;
+pub mod download;
+pub mod export;
+pub mod group;
+pub mod import;
+pub mod journal;
+pub mod license;
 pub mod logger;
+pub mod meta;
+pub mod persist;
+pub mod plan;
+pub mod preview;
+pub mod rich;
 pub mod rvuid;
 pub mod target;
+pub mod tui;
+pub mod validate;
+pub mod wiki;
@@ -0,0 +1,214 @@
+use serde::Deserialize;
+
+use crate::target::YamlData;
+
+/// A license, from the short name a wiki gives it (`CC BY-SA 4.0`, `Public domain`), as an
+/// SPDX-like identifier (`CC-BY-SA-4.0`, `PD`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct License {
+    pub id: String,
+    /// The identifier without its version or jurisdiction, like `CC-BY-SA`.
+    pub family: String,
+    pub attribution: bool,
+    pub share_alike: bool,
+}
+
+/// Families that require derivatives to be shared under the same terms.
+const COPYLEFT: &[&str] = &["GFDL", "GPL", "LGPL", "AGPL", "FAL", "ODBL"];
+
+/// Shorthand and wordings wikis use, with the identifier they mean.
+const ALIASES: &[(&str, &str)] = &[
+    ("PUBLIC-DOMAIN", "PD"),
+    ("PUBLIC-DOMAIN-MARK", "PD"),
+    ("PDM", "PD"),
+    ("NO-RESTRICTIONS", "PD"),
+    ("CC-ZERO", "CC0"),
+];
+
+/// Uppercased, with spaces and underscores as dashes, so `cc by-sa 4.0` reads as `CC-BY-SA-4.0`.
+fn normalize(s: &str) -> String {
+    s.replace('"', "")
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_uppercase()
+}
+
+impl License {
+    /// Parse a license short name. `attribution_required` is the wiki's own say, where it has one.
+    pub fn parse(short: &str, attribution_required: Option<bool>) -> Self {
+        let mut id = normalize(short);
+        if id.is_empty() {
+            id = "UNKNOWN".to_string();
+        }
+        if let Some((_, alias)) = ALIASES.iter().find(|(name, _)| *name == id) {
+            id = alias.to_string();
+        }
+        if id.starts_with("PD-") {
+            // Commons tags like PD-old or PD-US are all public domain in the end.
+            id = "PD".to_string();
+        }
+        let parts: Vec<&str> = id.split('-').collect();
+        let family = if parts[0] == "CC" {
+            parts
+                .iter()
+                .take_while(|p| ["CC", "BY", "SA", "NC", "ND"].contains(p))
+                .copied()
+                .collect::<Vec<_>>()
+                .join("-")
+        } else {
+            // CC0-1.0, GFDL-1.2, GPL-3.0...
+            parts[0].to_string()
+        };
+        let family_parts: Vec<&str> = family.split('-').collect();
+        let share_alike = family_parts.contains(&"SA") || COPYLEFT.contains(&family.as_str());
+        let attribution = attribution_required
+            .unwrap_or(family_parts.contains(&"BY") || family == "ATTRIBUTION" || share_alike);
+        Self {
+            id,
+            family,
+            attribution,
+            share_alike,
+        }
+    }
+
+    /// The license of a downloaded target, from its metadata.
+    pub fn of(meta: &YamlData) -> Self {
+        Self::parse(
+            &meta.license,
+            meta.license_meta
+                .get("AttributionRequired")
+                .and_then(attribution_required),
+        )
+    }
+
+    /// Whether the license lets the file be passed on to others at all.
+    pub fn redistributable(&self) -> bool {
+        self.family == "PD"
+            || self.family == "CC0"
+            || self.family.starts_with("CC-")
+            || self.family == "ATTRIBUTION"
+            || COPYLEFT.contains(&self.family.as_str())
+    }
+
+    /// Whether a policy entry names this license: its exact identifier, its family (any version),
+    /// or a prefix ending in `*`.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = normalize(pattern);
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.id.starts_with(prefix.trim_end_matches('-')),
+            None => {
+                pattern == self.id
+                    || pattern == self.family
+                    || License::parse(&pattern, None).id == self.id
+            }
+        }
+    }
+}
+
+/// Which licenses a pool takes. An empty `allow` accepts any license that isn't denied.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Whether share-alike and other copyleft licenses are acceptable.
+    pub share_alike: bool,
+    /// Whether licenses that require crediting the author are acceptable.
+    pub attribution: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            share_alike: true,
+            attribution: true,
+        }
+    }
+}
+
+impl Policy {
+    /// What `--free-only` asks for: licenses that let targets be rebundled freely.
+    pub fn free() -> Self {
+        Self {
+            allow: vec!["CC0".to_string(), "PD".to_string(), "CC-BY".to_string()],
+            share_alike: false,
+            ..Default::default()
+        }
+    }
+
+    /// Why a license isn't acceptable, if it isn't.
+    pub fn check(&self, license: &License) -> Result<(), String> {
+        if let Some(pattern) = self.deny.iter().find(|p| license.matches(p)) {
+            return Err(format!("{} is denied by '{}'", license.id, pattern));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| license.matches(p)) {
+            return Err(format!("{} isn't an allowed license", license.id));
+        }
+        if license.share_alike && !self.share_alike {
+            return Err(format!("{} is share-alike", license.id));
+        }
+        if license.attribution && !self.attribution {
+            return Err(format!("{} requires attribution", license.id));
+        }
+        Ok(())
+    }
+}
+
+/// Read the wiki's `AttributionRequired` metadata, which is usually the string `true` or `false`.
+pub fn attribution_required(value: &serde_json::Value) -> Option<bool> {
+    match value {
+        serde_json::Value::Bool(b) => Some(*b),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let parse = |s: &str| License::parse(s, None);
+        assert_eq!(parse("CC BY-SA 4.0").id, "CC-BY-SA-4.0");
+        assert_eq!(parse("CC BY-SA 4.0").family, "CC-BY-SA");
+        assert_eq!(parse("Public domain").id, "PD");
+        assert_eq!(parse("PD-old-70").id, "PD");
+        assert_eq!(parse("").id, "UNKNOWN");
+
+        let free = Policy::free();
+        for ok in [
+            "CC0",
+            "CC0 1.0",
+            "Public domain",
+            "CC BY 2.5",
+            "CC-BY-4.0",
+            "cc by",
+        ] {
+            assert_eq!(free.check(&parse(ok)), Ok(()), "{}", ok);
+        }
+        for bad in ["CC BY-SA 3.0", "GFDL", "CC BY-NC 4.0", "Fair use", ""] {
+            assert!(free.check(&parse(bad)).is_err(), "{}", bad);
+        }
+
+        let policy = Policy {
+            deny: vec!["CC-BY-SA-2.0".to_string(), "GFDL*".to_string()],
+            attribution: false,
+            ..Default::default()
+        };
+        assert!(policy.check(&parse("CC0")).is_ok());
+        assert_eq!(
+            policy.check(&parse("GFDL-1.2")),
+            Err("GFDL-1.2 is denied by 'GFDL*'".to_string())
+        );
+        assert_eq!(
+            policy.check(&parse("CC BY 4.0")),
+            Err("CC-BY-4.0 requires attribution".to_string())
+        );
+        assert!(policy.check(&License::parse("CC0", Some(true))).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The language picked from multilingual metadata when none is asked for.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Readable text for the metadata people look at, cleaned from the wiki's HTML. The raw values
+/// are kept alongside it in the sidecar.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CleanMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage_terms: Option<String>,
+}
+
+impl CleanMeta {
+    /// Clean the fields from `get`, which looks up the wiki's metadata by name (`ImageDescription`,
+    /// `Artist`...).
+    pub fn new<'a>(get: impl Fn(&str) -> Option<&'a Value>, lang: &str) -> Self {
+        let clean = |key: &str| get(key).and_then(|v| text(v, lang));
+        Self {
+            title: clean("ObjectName"),
+            description: clean("ImageDescription"),
+            datetime: clean("DateTimeOriginal"),
+            artist: clean("Artist"),
+            credit: clean("Credit"),
+            usage_terms: clean("UsageTerms"),
+        }
+    }
+}
+
+/// Readable text from a metadata value: the value in `lang` if it has several languages, with
+/// HTML tags stripped and entities decoded. `None` if there's nothing left.
+pub fn text(value: &Value, lang: &str) -> Option<String> {
+    let raw = match pick_language(value, lang)? {
+        Value::String(s) => s.clone(),
+        Value::Null => return None,
+        other => other.to_string(),
+    };
+    Some(clean_html(&raw)).filter(|s| !s.is_empty())
+}
+
+/// The value for `lang` from a multilingual value (an object keyed by language code, like
+/// `iiextmetadatamultilang` gives), falling back to English and then any language. Other values
+/// are returned as they are.
+pub fn pick_language<'a>(value: &'a Value, lang: &str) -> Option<&'a Value> {
+    let Value::Object(by_lang) = value else {
+        return Some(value);
+    };
+    by_lang
+        .get(lang)
+        .or_else(|| by_lang.get(DEFAULT_LANGUAGE))
+        .or_else(|| {
+            by_lang
+                .iter()
+                .find(|(code, _)| !code.starts_with('_'))
+                .map(|(_, v)| v)
+        })
+}
+
+/// Tags that start a new line.
+const BLOCK_TAGS: &[&str] = &[
+    "br", "p", "div", "li", "tr", "ul", "ol", "table", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// Turn an HTML fragment into plain text: tags stripped (blocks and `<br>` become line breaks),
+/// entities decoded and whitespace collapsed.
+pub fn clean_html(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    let mut skipping: Option<String> = None;
+    // Line breaks in the source are just whitespace, like a browser treats them.
+    let push = |text: &mut String, s: &str| text.push_str(&s.replace(['\r', '\n'], " "));
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push(&mut text, &rest[..start]);
+        }
+        let Some(len) = rest[start..].find('>') else {
+            // Not a tag after all.
+            if skipping.is_none() {
+                push(&mut text, &rest[start..]);
+            }
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + len];
+        rest = &rest[start + len + 1..];
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+        if let Some(skipped) = &skipping {
+            if closing && &name == skipped {
+                skipping = None;
+            }
+            continue;
+        }
+        if !closing && (name == "script" || name == "style") {
+            skipping = Some(name);
+        } else if BLOCK_TAGS.contains(&name.as_str()) {
+            text.push('\n');
+        }
+    }
+    if skipping.is_none() {
+        push(&mut text, rest);
+    }
+    decode_entities(&text)
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| {
+                let entity = &rest[1..end + 1];
+                let c = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some(' '),
+                    _ => entity
+                        .strip_prefix("#x")
+                        .or_else(|| entity.strip_prefix("#X"))
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                        .and_then(char::from_u32),
+                };
+                c.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_metadata() {
+        assert_eq!(
+            clean_html(
+                "<a href=\"//commons.wikimedia.org/wiki/User:Jane\" title=\"User:Jane\">Jane&nbsp;Doe</a>"
+            ),
+            "Jane Doe"
+        );
+        assert_eq!(
+            clean_html(
+                "<div class=\"description\">A <b>lighthouse</b>\n  at dusk &amp; the sea<br/>Maine &#x2014; 1999</div><style>.x{}</style>"
+            ),
+            "A lighthouse at dusk & the sea\nMaine \u{2014} 1999"
+        );
+        assert_eq!(clean_html("5 < 6 & R&D"), "5 < 6 & R&D");
+
+        let multilang = serde_json::json!({"de": "Ein <i>Leuchtturm</i>", "en": "A lighthouse", "_type": "lang"});
+        assert_eq!(text(&multilang, "de").unwrap(), "Ein Leuchtturm");
+        assert_eq!(text(&multilang, "fr").unwrap(), "A lighthouse");
+        assert_eq!(text(&serde_json::json!(1999), "en").unwrap(), "1999");
+        assert_eq!(text(&serde_json::json!("<br>"), "en"), None);
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::Context;
+use log::debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// How many old copies of the completed targets are kept, as `<file>.bak.1` (newest) up to
+/// `<file>.bak.5`.
+pub const BACKUPS: usize = 5;
+
+/// An exclusive advisory lock on a state file, held until dropped. It's taken on a `<file>.lock`
+/// next to it, since the file itself gets replaced on every write.
+pub struct FileLock {
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Block until we hold the lock for `path`.
+pub fn lock(path: &Path) -> anyhow::Result<FileLock> {
+    let lock_path = sibling(path, ".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open lock file {}", lock_path.display()))?;
+    debug!("Locking {}", lock_path.display());
+    file.lock()
+        .with_context(|| format!("failed to lock {}", lock_path.display()))?;
+    Ok(FileLock { file })
+}
+
+/// Write `bytes` to a temporary file next to `path` and rename it over `path`, so readers (and a
+/// crash halfway through) only ever see the old or the new contents.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp = sibling(path, &format!(".tmp.{}", std::process::id()));
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    sibling(path, &format!(".bak.{}", n))
+}
+
+/// Shift `<file>.bak.1` .. `<file>.bak.<keep>` along by one, dropping the oldest, and copy the
+/// current file to `<file>.bak.1`.
+pub fn rotate_backups(path: &Path, keep: usize) -> anyhow::Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+    for n in (1..keep).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))
+        .with_context(|| format!("failed to back up {}", path.display()))?;
+    Ok(())
+}
+
+/// Parse a YAML state file, or the default (empty) value if it doesn't exist yet.
+pub fn read_yaml<T: DeserializeOwned + Default>(path: &Path) -> anyhow::Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let text = fs::read_to_string(path)?;
+    serde_yaml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Replace a YAML state file with `value` under its lock, keeping `backups` old copies.
+pub fn write_yaml<T: Serialize>(path: &Path, value: &T, backups: usize) -> anyhow::Result<()> {
+    let _lock = lock(path)?;
+    write_yaml_locked(path, value, backups)
+}
+
+/// Read-modify-write a YAML state file while holding its lock, so concurrent runs don't clobber
+/// each other's changes. Nothing is written if `f` fails.
+pub fn update_yaml<T, R, F>(path: &Path, backups: usize, f: F) -> anyhow::Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+    F: FnOnce(&mut T) -> anyhow::Result<R>,
+{
+    let _lock = lock(path)?;
+    let mut value: T = read_yaml(path)?;
+    let result = f(&mut value)?;
+    write_yaml_locked(path, &value, backups)?;
+    Ok(result)
+}
+
+fn write_yaml_locked<T: Serialize>(path: &Path, value: &T, backups: usize) -> anyhow::Result<()> {
+    let yaml = serde_yaml::to_string(value)?;
+    rotate_backups(path, backups)?;
+    write_atomic(path, yaml.as_bytes())
+}
+
+/// Read what a command line argument names: stdin for `-`, otherwise the (tilde-expanded) file.
+pub fn read_input(arg: &str) -> anyhow::Result<String> {
+    if arg == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .context("failed to read stdin")?;
+        return Ok(text);
+    }
+    let path = shellexpand::tilde(arg).into_owned();
+    fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))
+}
+
+/// Whether `name` is only file and directory names, so joining it onto a directory can't point
+/// outside it.
+pub fn is_contained(name: &str) -> bool {
+    !name.is_empty()
+        && Path::new(name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_yaml_rotates_backups() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("state.yaml");
+        for i in 0..4u32 {
+            update_yaml(&path, 2, |v: &mut Vec<u32>| {
+                v.push(i);
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert_eq!(read_yaml::<Vec<u32>>(&path).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(
+            read_yaml::<Vec<u32>>(&backup_path(&path, 1)).unwrap(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            read_yaml::<Vec<u32>>(&backup_path(&path, 2)).unwrap(),
+            vec![0, 1]
+        );
+        assert!(!backup_path(&path, 3).exists());
+
+        let failed: anyhow::Result<()> = update_yaml(&path, 2, |v: &mut Vec<u32>| {
+            v.clear();
+            anyhow::bail!("nope")
+        });
+        assert!(failed.is_err());
+        assert_eq!(read_yaml::<Vec<u32>>(&path).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::license::License;
+use crate::persist;
+use crate::target::Rendition;
+
+/// What a download run will do, worked out from the searches without downloading anything. Saved
+/// with `--plan` and run as is with `--apply-plan`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Plan {
+    pub pool: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub files: Vec<PlannedFile>,
+    #[serde(default)]
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// A file to download, with everything needed to save it and its metadata.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlannedFile {
+    pub title: String,
+    pub query: String,
+    #[serde(default)]
+    pub frontloading: Vec<String>,
+    /// The name it's saved as in the pool.
+    pub file: String,
+    pub license: String,
+    /// Already in the pool, so only its metadata is written.
+    #[serde(default)]
+    pub present: bool,
+    /// Checked once a thumbnail is downloaded, since its size isn't known before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    pub rendition: Rendition,
+    #[serde(default)]
+    pub extmetadata: HashMap<String, serde_json::Value>,
+}
+
+/// A search result that won't be downloaded, and why.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SkippedFile {
+    pub title: String,
+    pub query: String,
+    pub reason: String,
+}
+
+/// The totals for a plan.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub to_download: usize,
+    pub present: usize,
+    pub skipped: usize,
+    /// The known sizes of the files to download.
+    pub bytes: u64,
+    /// How many files to download have no size until they're downloaded (usually thumbnails).
+    pub unknown_size: usize,
+    /// Files to download or already present, by license identifier.
+    pub licenses: BTreeMap<String, usize>,
+}
+
+/// Whether `name` is a single file name, so it's saved right in the pool and nowhere else.
+pub fn is_file_name(name: &str) -> bool {
+    persist::is_contained(name) && Path::new(name).components().count() == 1
+}
+
+impl Plan {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let path = shellexpand::tilde(path).into_owned();
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("failed to read plan {}", path))?;
+        let plan: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("failed to parse plan {}", path))?;
+        for file in &plan.files {
+            if !is_file_name(&file.file) {
+                anyhow::bail!("invalid file name '{}' in plan {}", file.file, path);
+            }
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let path = shellexpand::tilde(path).into_owned();
+        persist::write_atomic(Path::new(&path), serde_yaml::to_string(self)?.as_bytes())
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary {
+            skipped: self.skipped.len(),
+            ..Default::default()
+        };
+        for file in &self.files {
+            let license = License::parse(&file.license, None);
+            *summary.licenses.entry(license.id).or_default() += 1;
+            if file.present {
+                summary.present += 1;
+                continue;
+            }
+            summary.to_download += 1;
+            match file.rendition.bytes {
+                Some(bytes) => summary.bytes += bytes,
+                None => summary.unknown_size += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// A size in bytes for people, like `1.5 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = u;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_summary() {
+        let file = |title: &str, license: &str, bytes: Option<u64>, present: bool| PlannedFile {
+            title: title.to_string(),
+            query: "lighthouse".to_string(),
+            frontloading: Vec::new(),
+            file: title.trim_start_matches("File:").to_string(),
+            license: license.to_string(),
+            present,
+            max_bytes: None,
+            rendition: Rendition {
+                bytes,
+                ..Default::default()
+            },
+            extmetadata: HashMap::new(),
+        };
+        let plan = Plan {
+            pool: "lights".to_string(),
+            created_at: None,
+            files: vec![
+                file("File:A.jpg", "CC BY-SA 4.0", Some(1_500_000), false),
+                file("File:B.jpg", "CC-BY-SA-4.0", None, false),
+                file("File:C.jpg", "CC0", Some(200), true),
+            ],
+            skipped: vec![SkippedFile {
+                title: "File:D.jpg".to_string(),
+                query: "lighthouse".to_string(),
+                reason: "width 600 is under 800".to_string(),
+            }],
+        };
+        let text = serde_yaml::to_string(&plan).unwrap();
+        let plan: Plan = serde_yaml::from_str(&text).unwrap();
+        assert_eq!(
+            plan.summary(),
+            Summary {
+                to_download: 2,
+                present: 1,
+                skipped: 1,
+                bytes: 1_500_000,
+                unknown_size: 1,
+                licenses: [("CC-BY-SA-4.0".to_string(), 2), ("CC0".to_string(), 1)]
+                    .into_iter()
+                    .collect(),
+            }
+        );
+        assert_eq!(format_bytes(1_500_000), "1.5 MB");
+        assert_eq!(format_bytes(999), "999 B");
+    }
+
+    #[test]
+    fn test_file_names_stay_in_the_pool() {
+        assert!(is_file_name("Lighthouse_at_dusk.jpg"));
+        for name in ["", "..", "../x.jpg", "/etc/x.jpg", "a/b.jpg", "./x.jpg"] {
+            assert!(!is_file_name(name), "{}", name);
+        }
+    }
+}
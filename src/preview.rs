@@ -0,0 +1,361 @@
+use anyhow::Context;
+use data_encoding::BASE64;
+use image::RgbaImage;
+use image::imageops::FilterType;
+use log::debug;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::target::TargetType;
+
+/// Pixel size of a terminal cell when the terminal won't tell us.
+const DEFAULT_CELL_PX: (u32, u32) = (10, 20);
+/// Rows left free under the image for the prompts that follow the reveal.
+const RESERVED_ROWS: u32 = 3;
+const KITTY_CHUNK: usize = 4096;
+
+/// How to draw images inline in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreviewMode {
+    /// Pick the best protocol the terminal looks like it supports.
+    Auto,
+    Kitty,
+    Sixel,
+    /// Unicode half blocks with 24-bit colour, works nearly everywhere.
+    Blocks,
+    None,
+}
+
+impl FromStr for PreviewMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(PreviewMode::Auto),
+            "kitty" => Ok(PreviewMode::Kitty),
+            "sixel" => Ok(PreviewMode::Sixel),
+            "blocks" => Ok(PreviewMode::Blocks),
+            "none" => Ok(PreviewMode::None),
+            _ => anyhow::bail!(
+                "unknown preview mode '{}' (auto, kitty, sixel, blocks, none)",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PreviewMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PreviewMode::Auto => "auto",
+            PreviewMode::Kitty => "kitty",
+            PreviewMode::Sixel => "sixel",
+            PreviewMode::Blocks => "blocks",
+            PreviewMode::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl PreviewMode {
+    /// Resolve `Auto` from the environment the terminal advertises.
+    pub fn detect(self) -> Self {
+        if self != PreviewMode::Auto {
+            return self;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        let detected = if term == "xterm-kitty"
+            || env::var_os("KITTY_WINDOW_ID").is_some()
+            || matches!(term_program.as_str(), "WezTerm" | "ghostty")
+        {
+            PreviewMode::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term == "mlterm"
+            || matches!(term_program.as_str(), "iTerm.app" | "mlterm" | "contour")
+        {
+            PreviewMode::Sixel
+        } else {
+            PreviewMode::Blocks
+        };
+        debug!(
+            "Detected preview mode {} (TERM={}, TERM_PROGRAM={})",
+            detected, term, term_program
+        );
+        detected
+    }
+}
+
+/// Whether opening a file in a desktop application is likely to work, false over SSH or on a
+/// headless machine.
+pub fn gui_available() -> bool {
+    let has_display = env::var_os("DISPLAY").is_some() || env::var_os("WAYLAND_DISPLAY").is_some();
+    if env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some() {
+        return has_display;
+    }
+    if cfg!(any(target_os = "macos", target_os = "windows")) {
+        true
+    } else {
+        has_display
+    }
+}
+
+/// Draw an image target inline in the terminal.
+pub fn show(path: &Path, target_type: &TargetType, mode: PreviewMode) -> anyhow::Result<()> {
+    let mode = mode.detect();
+    if mode == PreviewMode::None {
+        return Ok(());
+    }
+    let img = load(path, target_type)?;
+    let (cols, rows, cell_w, cell_h) = terminal_geometry();
+    let rows = rows.saturating_sub(RESERVED_ROWS).max(1);
+    let out = match mode {
+        PreviewMode::Blocks => {
+            // Each cell shows two pixels stacked, which is roughly square.
+            let img = fit(&img, cols, rows * 2);
+            encode_blocks(&img)
+        }
+        PreviewMode::Sixel => encode_sixel(&fit(&img, cols * cell_w, rows * cell_h)),
+        PreviewMode::Kitty => encode_kitty(&fit(&img, cols * cell_w, rows * cell_h))?,
+        PreviewMode::Auto | PreviewMode::None => unreachable!(),
+    };
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(out.as_bytes())?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn load(path: &Path, target_type: &TargetType) -> anyhow::Result<RgbaImage> {
+    let bytes =
+        fs::read(path).with_context(|| format!("failed to read bytes from {}", path.display()))?;
+    if *target_type == TargetType::Svg {
+        return rasterize_svg(&bytes);
+    }
+    let img = image::load_from_memory(&bytes)
+        .with_context(|| format!("failed to decode {}", path.display()))?;
+    Ok(img.to_rgba8())
+}
+
+fn rasterize_svg(bytes: &[u8]) -> anyhow::Result<RgbaImage> {
+    let tree = resvg::usvg::Tree::from_data(bytes, &resvg::usvg::Options::default())?;
+    let size = tree.size().to_int_size();
+    // Small SVGs are scaled up so there's something to look at, they're vectors after all.
+    let scale = (1024.0 / size.width().max(size.height()) as f32).max(1.0);
+    let width = (size.width() as f32 * scale).ceil() as u32;
+    let height = (size.height() as f32 * scale).ceil() as u32;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| anyhow::anyhow!("SVG has an empty canvas"))?;
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    let mut img = RgbaImage::new(width, height);
+    for (px, out) in pixmap.pixels().iter().zip(img.pixels_mut()) {
+        let c = px.demultiply();
+        *out = image::Rgba([c.red(), c.green(), c.blue(), c.alpha()]);
+    }
+    Ok(img)
+}
+
+/// Columns, rows and the pixel size of a cell.
+fn terminal_geometry() -> (u32, u32, u32, u32) {
+    let (cols, rows, width, height) = match crossterm::terminal::window_size() {
+        Ok(ws) => (ws.columns, ws.rows, ws.width, ws.height),
+        Err(_) => {
+            let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+            (cols, rows, 0, 0)
+        }
+    };
+    let (cols, rows) = (cols.max(1) as u32, rows.max(1) as u32);
+    let (cell_w, cell_h) = if width > 0 && height > 0 {
+        (width as u32 / cols, height as u32 / rows)
+    } else {
+        DEFAULT_CELL_PX
+    };
+    (cols, rows, cell_w.max(1), cell_h.max(1))
+}
+
+/// Scale down (never up) to fit inside the box, keeping the aspect ratio.
+fn fit(img: &RgbaImage, max_w: u32, max_h: u32) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    let scale = (max_w as f64 / w as f64)
+        .min(max_h as f64 / h as f64)
+        .min(1.0);
+    let new_w = ((w as f64 * scale).round() as u32).max(1);
+    let new_h = ((h as f64 * scale).round() as u32).max(1);
+    if (new_w, new_h) == (w, h) {
+        img.clone()
+    } else {
+        image::imageops::resize(img, new_w, new_h, FilterType::Triangle)
+    }
+}
+
+/// Composite over black, the terminal background is usually dark.
+fn flatten(px: &image::Rgba<u8>) -> [u8; 3] {
+    let a = px[3] as u16;
+    [0, 1, 2].map(|i| (px[i] as u16 * a / 255) as u8)
+}
+
+/// Colours of the top and bottom half of each terminal cell.
+pub type BlockCells = Vec<Vec<([u8; 3], [u8; 3])>>;
+
+/// Load an image scaled to fit in `cols` x `rows` terminal cells, as half-block colours, for
+/// drawing it inside a larger terminal UI.
+pub fn block_cells(
+    path: &Path,
+    target_type: &TargetType,
+    cols: u32,
+    rows: u32,
+) -> anyhow::Result<BlockCells> {
+    let img = load(path, target_type)?;
+    Ok(to_cells(&fit(&img, cols.max(1), rows.max(1) * 2)))
+}
+
+fn to_cells(img: &RgbaImage) -> BlockCells {
+    let (w, h) = img.dimensions();
+    (0..h)
+        .step_by(2)
+        .map(|y| {
+            (0..w)
+                .map(|x| {
+                    let top = flatten(img.get_pixel(x, y));
+                    let bottom = if y + 1 < h {
+                        flatten(img.get_pixel(x, y + 1))
+                    } else {
+                        [0, 0, 0]
+                    };
+                    (top, bottom)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn encode_blocks(img: &RgbaImage) -> String {
+    let mut out = String::new();
+    for row in to_cells(img) {
+        for ([tr, tg, tb], [br, bg, bb]) in row {
+            out.push_str(&format!(
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                tr, tg, tb, br, bg, bb
+            ));
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+fn encode_kitty(img: &RgbaImage) -> anyhow::Result<String> {
+    let mut png = Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageFormat::Png)?;
+    let encoded = BASE64.encode(png.get_ref());
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        let chunk = std::str::from_utf8(chunk)?;
+        if i == 0 {
+            out.push_str(&format!("\x1b_Gf=100,a=T,m={};{}\x1b\\", more, chunk));
+        } else {
+            out.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+        }
+    }
+    Ok(out)
+}
+
+/// Sixel with a fixed 6x6x6 colour cube, which is plenty for a feedback preview.
+fn encode_sixel(img: &RgbaImage) -> String {
+    let (w, h) = img.dimensions();
+    let level = |v: u8| (v as u16 * 5 / 255) as usize;
+    let index = |px: &image::Rgba<u8>| -> Option<usize> {
+        if px[3] < 128 {
+            return None;
+        }
+        Some(level(px[0]) * 36 + level(px[1]) * 6 + level(px[2]))
+    };
+    let mut out = format!("\x1bPq\"1;1;{};{}", w, h);
+    for i in 0..216 {
+        let (r, g, b) = (i / 36, (i / 6) % 6, i % 6);
+        out.push_str(&format!("#{};2;{};{};{}", i, r * 20, g * 20, b * 20));
+    }
+    for band in (0..h).step_by(6) {
+        let mut colors: Vec<usize> = Vec::new();
+        let mut row_bits: Vec<Vec<u8>> = vec![vec![0u8; w as usize]; 216];
+        for x in 0..w {
+            for bit in 0..6 {
+                let y = band + bit;
+                if y >= h {
+                    break;
+                }
+                if let Some(c) = index(img.get_pixel(x, y)) {
+                    if !colors.contains(&c) {
+                        colors.push(c);
+                    }
+                    row_bits[c][x as usize] |= 1 << bit;
+                }
+            }
+        }
+        for (n, c) in colors.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            out.push_str(&format!("#{}", c));
+            push_sixel_run(&mut out, &row_bits[*c]);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_sixel_run(out: &mut String, bits: &[u8]) {
+    let mut i = 0;
+    while i < bits.len() {
+        let mut run = 1;
+        while i + run < bits.len() && bits[i + run] == bits[i] {
+            run += 1;
+        }
+        let ch = (bits[i] + 63) as char;
+        if run >= 4 {
+            out.push_str(&format!("!{}{}", run, ch));
+        } else {
+            (0..run).for_each(|_| out.push(ch));
+        }
+        i += run;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_keeps_aspect() {
+        let img = RgbaImage::new(400, 200);
+        assert_eq!(fit(&img, 100, 100).dimensions(), (100, 50));
+        assert_eq!(fit(&img, 1000, 1000).dimensions(), (400, 200));
+    }
+
+    #[test]
+    fn test_encode_sixel() {
+        let img = RgbaImage::from_pixel(8, 6, image::Rgba([255, 0, 0, 255]));
+        let sixel = encode_sixel(&img);
+        assert!(sixel.starts_with("\x1bPq\"1;1;8;6"));
+        // Pure red is colour 180 in the cube, one band with all six bits set.
+        assert!(sixel.contains("#180!8~-"));
+        assert!(sixel.ends_with("\x1b\\"));
+    }
+
+    #[test]
+    fn test_preview_mode_from_str() {
+        assert_eq!("Sixel".parse::<PreviewMode>().unwrap(), PreviewMode::Sixel);
+        assert!("ascii".parse::<PreviewMode>().is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// A hand-written target in Markdown with an optional YAML front-matter block, like:
+///
+/// ```text
+/// ---
+/// title: Eiffel Tower
+/// coordinates: 48.8584, 2.2945
+/// date: 1889-03-31
+/// feedback: feedback/eiffel.jpg
+/// frontloading: [structure]
+/// ---
+/// The Eiffel Tower on the day of its inauguration.
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RichTarget {
+    pub title: Option<String>,
+    pub coordinates: Option<String>,
+    pub date: Option<String>,
+    pub feedback: Option<String>,
+    pub frontloading: Vec<String>,
+    pub body: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FrontMatter {
+    title: Option<String>,
+    coordinates: Option<String>,
+    date: Option<String>,
+    feedback: Option<String>,
+    #[serde(default)]
+    frontloading: Vec<String>,
+}
+
+impl RichTarget {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let (front, body) = split_front_matter(text)?;
+        let fm: FrontMatter = match front {
+            Some(yaml) if !yaml.trim().is_empty() => serde_yaml::from_str(yaml)?,
+            _ => FrontMatter::default(),
+        };
+        Ok(Self {
+            title: fm.title,
+            coordinates: fm.coordinates,
+            date: fm.date,
+            feedback: fm.feedback,
+            frontloading: fm.frontloading,
+            body: body.trim().to_string(),
+        })
+    }
+
+    /// The feedback image, relative to the directory of the target file unless it's absolute.
+    pub fn feedback_path(&self, target_path: &Path) -> Option<PathBuf> {
+        let feedback = self.feedback.as_ref()?;
+        let expanded = PathBuf::from(shellexpand::tilde(feedback).into_owned());
+        if expanded.is_absolute() {
+            Some(expanded)
+        } else {
+            let dir = target_path.parent().unwrap_or(Path::new("."));
+            Some(dir.join(expanded))
+        }
+    }
+
+    /// The non-empty fields in display order.
+    pub fn iter_fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("Title", &self.title),
+            ("Coordinates", &self.coordinates),
+            ("Date", &self.date),
+            ("Feedback", &self.feedback),
+        ]
+        .into_iter()
+        .filter_map(|(key, val)| val.as_deref().map(|v| (key, v)))
+        .collect()
+    }
+}
+
+fn split_front_matter(text: &str) -> anyhow::Result<(Option<&str>, &str)> {
+    let text = text.trim_start_matches('\u{feff}');
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return Ok((None, text));
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Ok((Some(&rest[..offset]), &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    anyhow::bail!("front-matter is missing its closing '---'");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_front_matter() {
+        let text = "---\ntitle: Eiffel Tower\ncoordinates: 48.8584, 2.2945\nfeedback: fb/eiffel.jpg\n---\n\nThe tower at its inauguration.\n";
+        let rich = RichTarget::parse(text).unwrap();
+        assert_eq!(rich.title.as_deref(), Some("Eiffel Tower"));
+        assert_eq!(rich.coordinates.as_deref(), Some("48.8584, 2.2945"));
+        assert_eq!(rich.date, None);
+        assert_eq!(rich.body, "The tower at its inauguration.");
+        assert_eq!(
+            rich.feedback_path(Path::new("/pool/eiffel.target.md")),
+            Some(PathBuf::from("/pool/fb/eiffel.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_parse_without_front_matter() {
+        let rich = RichTarget::parse("Just a body.\n").unwrap();
+        assert_eq!(rich.body, "Just a body.");
+        assert_eq!(rich.title, None);
+        assert!(RichTarget::parse("---\ntitle: unterminated\n").is_err());
+    }
+}
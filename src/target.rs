@@ -1,5 +1,6 @@
 use anyhow::Context;
-use log::{debug, info, warn};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
 use rand::prelude::IndexedRandom;
 use rand::rng;
 use serde::{Deserialize, Serialize};
@@ -7,12 +8,18 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
-use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
+use crate::bundle::{Bundle, ComponentPaths};
 use crate::cache::{CacheMap, CachedHash};
+use crate::journal::Journal;
+use crate::meta::{self, CleanMeta};
+use crate::persist;
+use crate::preview::{self, PreviewMode};
+use crate::rich::RichTarget;
 use crate::rvuid::Rvuid;
+use crate::validate;
 
 #[derive(Clone, Debug, serde::Serialize, Deserialize)]
 pub struct YamlData {
@@ -23,17 +30,77 @@ pub struct YamlData {
     pub img_metadata: HashMap<String, serde_json::Value>,
     pub license: String,
     pub license_meta: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendition: Option<Rendition>,
+    /// Readable text cleaned from the HTML in the raw fields above.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleaned: Option<CleanMeta>,
+}
+
+/// Which version of a downloaded file was saved: the original, or a smaller thumbnail of it the
+/// wiki rendered because the original didn't fit the pool's filters.
+#[derive(Clone, Debug, Default, Deserialize, serde::Serialize)]
+pub struct Rendition {
+    pub thumbnail: bool,
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime: Option<String>,
+    pub bytes: Option<u64>,
+    pub original_url: String,
+    pub original_width: Option<u32>,
+    pub original_height: Option<u32>,
+    pub original_mime: Option<String>,
+    pub original_bytes: Option<u64>,
 }
 
 impl YamlData {
+    /// Where a target's metadata is kept: next to it, with `.yaml` added to the name.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".yaml");
+        PathBuf::from(name)
+    }
+
+    /// Load a target's metadata, if it has any.
+    pub fn load_for(path: &Path) -> anyhow::Result<Option<Self>> {
+        let sidecar = Self::sidecar_path(path);
+        let Ok(text) = fs::read_to_string(&sidecar) else {
+            return Ok(None);
+        };
+        let meta = serde_yaml::from_str(&text)
+            .with_context(|| format!("failed to parse {}", sidecar.display()))?;
+        Ok(Some(meta))
+    }
+
+    /// The cleaned metadata, or for sidecars saved before it was kept, cleaned from the raw
+    /// fields now.
+    pub fn clean(&self) -> CleanMeta {
+        if let Some(ref cleaned) = self.cleaned {
+            return cleaned.clone();
+        }
+        CleanMeta::new(
+            |key| match key {
+                "ImageDescription" => Some(&self.image_description),
+                "DateTimeOriginal" => Some(&self.datetime_original),
+                _ => self
+                    .license_meta
+                    .get(key)
+                    .or_else(|| self.img_metadata.get(key)),
+            },
+            meta::DEFAULT_LANGUAGE,
+        )
+    }
+
     pub fn serialize(&self) -> HashMap<String, String> {
+        let cleaned = self.clean();
         let mut map: HashMap<String, String> = HashMap::new();
         map.insert("Query".to_string(), self.query.to_string());
         map.insert(
             "Description".to_string(),
-            self.image_description.to_string(),
+            cleaned.description.unwrap_or_default(),
         );
-        map.insert("Datetime".to_string(), self.datetime_original.to_string());
+        map.insert("Datetime".to_string(), cleaned.datetime.unwrap_or_default());
         map.insert("License".to_string(), self.license.to_string());
         map
     }
@@ -44,19 +111,52 @@ pub enum TargetType {
     Text,
     Jpeg,
     Svg,
+    Png,
+    Webp,
+    Gif,
+    Tiff,
+    Video,
+    Audio,
+    Markdown,
+    Bundle,
 }
 
 impl TargetType {
+    /// Guess the target type from the file extension alone.
     pub fn parse(path: &Path) -> Option<Self> {
+        if path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.to_ascii_lowercase().ends_with(".target.md"))
+        {
+            return Some(TargetType::Markdown);
+        }
+        if path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.to_ascii_lowercase().ends_with(".bundle.zip"))
+        {
+            return Some(TargetType::Bundle);
+        }
         let ext = path
             .extension()
             .and_then(OsStr::to_str)
             .map(|s| s.to_ascii_lowercase());
-        match ext {
-            Some(ext) if ext == "jpg" || ext == "jpeg" => Some(TargetType::Jpeg),
-            Some(ext) if ext == "svg" => Some(TargetType::Svg),
-            Some(ext) if ext == "target" => Some(TargetType::Text),
-            Some(ext) if ext == "yaml" || ext == "yml" => None,
+        match ext.as_deref() {
+            Some("jpg" | "jpeg") => Some(TargetType::Jpeg),
+            Some("svg") => Some(TargetType::Svg),
+            Some("png") => Some(TargetType::Png),
+            Some("webp") => Some(TargetType::Webp),
+            Some("gif") => Some(TargetType::Gif),
+            Some("tif" | "tiff") => Some(TargetType::Tiff),
+            Some("mp4" | "m4v" | "mov" | "webm" | "mkv" | "avi" | "ogv" | "mpg" | "mpeg") => {
+                Some(TargetType::Video)
+            }
+            Some("mp3" | "m4a" | "ogg" | "oga" | "opus" | "flac" | "wav") => {
+                Some(TargetType::Audio)
+            }
+            Some("target") => Some(TargetType::Text),
+            Some("yaml" | "yml") => None,
             None => {
                 warn!("Extension not parsed from: {}", path.display());
                 None
@@ -67,6 +167,112 @@ impl TargetType {
             }
         }
     }
+
+    /// Recognise the target type from the leading bytes of the file.
+    /// Text and SVG have no reliable signature, so those are left to the extension.
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        let riff_kind = if bytes.len() >= 12 && &bytes[..4] == b"RIFF" {
+            Some(&bytes[8..12])
+        } else {
+            None
+        };
+        let ftyp_brand = if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+            Some(&bytes[8..12])
+        } else {
+            None
+        };
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(TargetType::Jpeg)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(TargetType::Png)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(TargetType::Gif)
+        } else if bytes.starts_with(b"II*\x00") || bytes.starts_with(b"MM\x00*") {
+            Some(TargetType::Tiff)
+        } else if riff_kind == Some(b"WEBP") {
+            Some(TargetType::Webp)
+        } else if riff_kind == Some(b"AVI ") {
+            Some(TargetType::Video)
+        } else if riff_kind == Some(b"WAVE") {
+            Some(TargetType::Audio)
+        } else if let Some(brand) = ftyp_brand {
+            match brand {
+                b"M4A " | b"M4B " => Some(TargetType::Audio),
+                // HEIF/AVIF stills share the ISO container but aren't supported as targets.
+                b"heic" | b"heix" | b"mif1" | b"avif" => None,
+                _ => Some(TargetType::Video),
+            }
+        } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            // Matroska / WebM
+            Some(TargetType::Video)
+        } else if bytes.starts_with(b"OggS")
+            || bytes.starts_with(b"fLaC")
+            || bytes.starts_with(b"ID3")
+            || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0)
+        {
+            Some(TargetType::Audio)
+        } else {
+            None
+        }
+    }
+
+    /// Determine the target type from the file contents first, falling back to the extension for
+    /// the formats that have no signature of their own (text, markdown, SVG and bundles).
+    pub fn detect(path: &Path, bytes: &[u8]) -> Option<Self> {
+        if is_sidecar(path) {
+            return None;
+        }
+        if let Some(magic) = Self::from_magic(bytes) {
+            if let Some(by_ext) = Self::parse(path)
+                && by_ext != magic
+            {
+                debug!(
+                    "{} looks like {:?} but is named like {:?}",
+                    path.display(),
+                    magic,
+                    by_ext
+                );
+            }
+            return Some(magic);
+        }
+        match Self::parse(path) {
+            Some(
+                t
+                @ (TargetType::Text | TargetType::Markdown | TargetType::Svg | TargetType::Bundle),
+            ) => Some(t),
+            Some(t) => {
+                debug!(
+                    "{} is named like {:?} but has no matching signature",
+                    path.display(),
+                    t
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        matches!(
+            self,
+            TargetType::Jpeg
+                | TargetType::Svg
+                | TargetType::Png
+                | TargetType::Webp
+                | TargetType::Gif
+                | TargetType::Tiff
+        )
+    }
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    matches!(
+        path.extension()
+            .and_then(OsStr::to_str)
+            .map(|s| s.to_ascii_lowercase())
+            .as_deref(),
+        Some("yaml" | "yml")
+    )
 }
 
 #[derive(Clone, Debug)]
@@ -77,6 +283,8 @@ pub struct Target {
     pub frontloading: Vec<String>,
     pub target_type: TargetType,
     pub meta: HashMap<String, String>,
+    pub rich: Option<RichTarget>,
+    pub bundle: Option<Bundle>,
 }
 
 impl fmt::Display for Target {
@@ -87,13 +295,39 @@ impl fmt::Display for Target {
 
 impl Target {
     pub fn parse(path: &Path) -> anyhow::Result<Self> {
+        if Bundle::is_bundle_dir(path) {
+            let (bundle, rvuid) = Bundle::load(path)?;
+            return Self::with_meta(path, rvuid, TargetType::Bundle, None, Some(bundle));
+        }
         let target_bytes = fs::read(path)
             .with_context(|| format!("failed to read bytes from {}", path.display()))?;
+        let target_type = TargetType::detect(path, &target_bytes)
+            .ok_or_else(|| anyhow::anyhow!("no target at {}", path.display()))?;
+        if target_type == TargetType::Bundle {
+            let (bundle, rvuid) = Bundle::from_archive(path, &target_bytes)?;
+            return Self::with_meta(path, rvuid, target_type, None, Some(bundle));
+        }
+        validate::validate_bytes(&target_bytes, &target_type)
+            .with_context(|| format!("invalid target at {}", path.display()))?;
         let rvuid = Rvuid::from_bytes(&target_bytes);
+        let rich = if target_type == TargetType::Markdown {
+            let text = String::from_utf8_lossy(&target_bytes);
+            Some(RichTarget::parse(&text)?)
+        } else {
+            None
+        };
+        Self::with_meta(path, rvuid, target_type, rich, None)
+    }
+
+    fn with_meta(
+        path: &Path,
+        rvuid: Rvuid,
+        target_type: TargetType,
+        rich: Option<RichTarget>,
+        bundle: Option<Bundle>,
+    ) -> anyhow::Result<Self> {
         let maybe_meta_path = Self::maybe_yaml(path);
         let meta_path = maybe_meta_path.clone();
-        let target_type = TargetType::parse(path)
-            .ok_or_else(|| anyhow::anyhow!("no target at {}", path.display()))?;
         if let Some(unp_meta_path) = maybe_meta_path {
             let text = fs::read_to_string(unp_meta_path)?;
             let yaml_data: YamlData = serde_yaml::from_str(&text)?;
@@ -105,10 +339,15 @@ impl Target {
                 meta_path,
                 frontloading,
                 meta: yaml_data.clone().serialize(),
+                rich,
+                bundle,
             })
         } else {
             debug!("No metadata found for file: {:?}", path);
-            let frontloading: Vec<String> = Vec::new();
+            let frontloading: Vec<String> = rich
+                .as_ref()
+                .map(|r| r.frontloading.clone())
+                .unwrap_or_default();
             let blank: HashMap<String, String> = HashMap::new();
             Ok(Target {
                 rvuid,
@@ -117,6 +356,8 @@ impl Target {
                 meta_path,
                 frontloading,
                 meta: blank,
+                rich,
+                bundle,
             })
         }
     }
@@ -143,9 +384,11 @@ impl Target {
         let cached_hashes: Vec<CachedHash> = fs::read_dir(dir)?
             .filter_map(|entry| {
                 entry.ok().and_then(|e| {
-                    if let Some(ch) = cachemap.get(&e.path()) {
+                    if let Some(ch) = cachemap.get(&e.path()).filter(|ch| ch.is_fresh()) {
                         Some(ch.clone())
                     } else {
+                        // Parsing validates it, so a file changed since it was cached is checked
+                        // again rather than trusted.
                         let target: Option<Target> = Target::parse(&e.path()).ok();
                         if let Some(target) = target {
                             // Parsed a new one, so save this back to the mutable cachemap.
@@ -153,7 +396,9 @@ impl Target {
                             cachemap.insert(ch.path.clone(), ch.clone());
                             Some(ch)
                         } else {
-                            // Could be a YAML file for example, any non-target.
+                            // Could be a YAML file for example, any non-target, or a target that
+                            // no longer validates.
+                            cachemap.remove(&e.path());
                             None
                         }
                     }
@@ -171,7 +416,10 @@ impl Target {
     ) -> anyhow::Result<Self> {
         let cached_hashes = Self::all_from_dir(dir, completed_rvuids, cachemap)?;
         if cached_hashes.is_empty() {
-            anyhow::bail!("no JPG/JPEG/SVG or TARGET files found in {}", dir.display());
+            anyhow::bail!(
+                "no image, video, audio or TARGET files found in {}",
+                dir.display()
+            );
         }
 
         let mut rng = rng();
@@ -183,6 +431,101 @@ impl Target {
         chosen.try_into()
     }
 
+    /// Print everything about the target once the session is over, and open it in the default
+    /// application unless `skip_open` is set. Images are drawn inline in the terminal with the
+    /// `preview` mode when they can't be opened. Returns where a bundle's components were
+    /// extracted, which are deleted once it's dropped, so keep it until the viewer is done.
+    pub fn reveal(&self, skip_open: bool, preview: PreviewMode) -> anyhow::Result<ComponentPaths> {
+        let mut components = ComponentPaths::default();
+        println!("Path: {}", self.path.display());
+        match self.target_type {
+            TargetType::Text => match fs::read_to_string(&self.path) {
+                Ok(contents) => println!("Target Text:\n{}", contents),
+                _ => {
+                    if skip_open {
+                        error!("Failed to read target text. You may need to open it manually.");
+                    } else {
+                        open::that(&self.path)?;
+                    }
+                }
+            },
+            TargetType::Markdown => {
+                if let Some(ref rich) = self.rich {
+                    self.reveal_rich(rich, skip_open, preview)?;
+                }
+            }
+            TargetType::Bundle => {
+                if let Some(ref bundle) = self.bundle {
+                    components = Self::reveal_bundle(bundle, skip_open, preview)?;
+                }
+            }
+            // We don't open text files if we can read them above as text.
+            _ => show_file(&self.path, &self.target_type, skip_open, preview)?,
+        }
+        if let Some(ref meta_path) = self.meta_path {
+            println!("YAML meta: {}", meta_path.display());
+        }
+        if !self.meta.is_empty() {
+            for (key, val) in self.iter_meta() {
+                println!("{}: {}", key, val);
+            }
+        }
+        Ok(components)
+    }
+
+    fn reveal_rich(
+        &self,
+        rich: &RichTarget,
+        skip_open: bool,
+        preview: PreviewMode,
+    ) -> anyhow::Result<()> {
+        for (key, val) in rich.iter_fields() {
+            println!("{}: {}", key, val);
+        }
+        if !rich.body.is_empty() {
+            println!("Target Text:\n{}", rich.body);
+        }
+        if let Some(feedback) = rich.feedback_path(&self.path) {
+            if !feedback.exists() {
+                warn!("Feedback {} doesn't exist.", feedback.display());
+            } else if let Some(feedback_type) = TargetType::parse(&feedback) {
+                show_file(&feedback, &feedback_type, skip_open, preview)?;
+            } else if !skip_open {
+                open::that(&feedback)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reveal_bundle(
+        bundle: &Bundle,
+        skip_open: bool,
+        preview: PreviewMode,
+    ) -> anyhow::Result<ComponentPaths> {
+        if let Some(ref title) = bundle.title {
+            println!("Bundle: {}", title);
+        }
+        let components = bundle.component_paths()?;
+        for (i, (component, path)) in bundle.components.iter().zip(&components.paths).enumerate() {
+            println!("[{}/{}] {}", i + 1, bundle.components.len(), path.display());
+            if let Some(ref caption) = component.caption {
+                println!("Caption: {}", caption);
+            }
+            match component.target_type {
+                TargetType::Text => println!("{}", fs::read_to_string(path)?),
+                TargetType::Markdown => {
+                    let rich = RichTarget::parse(&fs::read_to_string(path)?)?;
+                    for (key, val) in rich.iter_fields() {
+                        println!("{}: {}", key, val);
+                    }
+                    println!("{}", rich.body);
+                }
+                ref t => show_file(path, t, skip_open, preview)?,
+            }
+        }
+        Ok(components)
+    }
+
     pub fn iter_meta(&self) -> Vec<(String, String)> {
         let keys: Vec<String> = vec![
             "Query".into(),
@@ -201,7 +544,26 @@ impl Target {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Open a file in its default application, or draw it in the terminal if it's an image and
+/// there's no GUI to open it in (or `skip_open` was passed).
+fn show_file(
+    path: &Path,
+    target_type: &TargetType,
+    skip_open: bool,
+    preview: PreviewMode,
+) -> anyhow::Result<()> {
+    let inline = skip_open || !preview::gui_available();
+    if inline && target_type.is_image() && preview != PreviewMode::None {
+        if let Err(e) = preview::show(path, target_type, preview) {
+            warn!("Failed to preview {}: {}", path.display(), e);
+        }
+    } else if !skip_open {
+        open::that(path)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CompletedTarget {
     pub rvuid: Rvuid,
     pub path: String,
@@ -209,10 +571,29 @@ pub struct CompletedTarget {
     pub hit: Option<bool>,
     pub score: Option<u32>,
     pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sketch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Set when the target was made eligible again. The entry is kept as history but no longer
+    /// stops the target from being picked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reopened_at: Option<DateTime<Utc>>,
+    /// Ties the entry to its events when the completed targets are a journal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// The group session this entry was part of, linking it to the other viewers' entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
 }
 
 impl CompletedTarget {
     pub fn parse(path: &str) -> anyhow::Result<Vec<Self>> {
+        if Journal::is_journal(path) {
+            return Journal::open(path).view();
+        }
         let expanded = shellexpand::tilde(path).into_owned();
         let pbuf = PathBuf::from(&expanded);
         if !pbuf.exists() {
@@ -223,61 +604,137 @@ impl CompletedTarget {
         let targs: Vec<Self> = serde_yaml::from_str(&text)?;
         Ok(targs)
     }
-    pub fn dump(completed_targets: &Vec<Self>, path: &str) -> anyhow::Result<()> {
+    /// The RVUIDs that shouldn't be picked again, skipping reopened entries.
+    pub fn completed_rvuids(completed_targets: &[Self]) -> Vec<Rvuid> {
+        completed_targets
+            .iter()
+            .filter(|t| t.reopened_at.is_none())
+            .map(|t| t.rvuid.clone())
+            .collect()
+    }
+
+    /// Change the completed targets on disk while holding the file's lock, so another run
+    /// finishing at the same time doesn't lose its session.
+    pub fn update<R>(
+        path: &str,
+        f: impl FnOnce(&mut Vec<Self>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        if Journal::is_journal(path) {
+            return Journal::open(path).update(f);
+        }
         let expanded = shellexpand::tilde(path).into_owned();
         let pbuf = PathBuf::from(&expanded);
-        debug!(
-            "Writing {} completed targets to {} ...",
-            completed_targets.len(),
-            pbuf.display()
-        );
-        let yaml = serde_yaml::to_string(completed_targets)?;
-        let mut file = File::create(pbuf.clone())?;
-        file.write_all(yaml.as_bytes())?;
-        debug!(
-            "Succesfully wrote {} completed targets to {}",
-            completed_targets.len(),
-            pbuf.display()
-        );
-        Ok(())
+        persist::update_yaml(&pbuf, persist::BACKUPS, f)
     }
-    pub fn interactive_ask_results(&mut self) {
+
+    /// Prompt on stdin for whether it was a hit, the score and any notes.
+    pub fn interactive_ask_results(&mut self) -> anyhow::Result<()> {
         print!("Was it a hit ([y]es, [n]o, otherwise not saved/recorded)? ");
-        io::stdout().flush().unwrap();
+        io::stdout().flush()?;
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        self.hit = match input.trim().to_lowercase().as_str() {
-            "y" | "yes" => Some(true),
-            "n" | "no" => Some(false),
-            _ => {
-                debug!("Not recording whether it's a hit or not.");
-                None
-            }
-        };
+        io::stdin().read_line(&mut input)?;
+        self.hit = parse_hit(&input).unwrap_or(None);
+        if self.hit.is_none() {
+            debug!("Not recording whether it's a hit or not.");
+        }
 
         print!("Score out of 100 (0 to 100 or otherwise not saved/recorded)? ");
-        io::stdout().flush().unwrap();
+        io::stdout().flush()?;
         input.clear();
-        io::stdin().read_line(&mut input).unwrap();
-        self.score = match input.trim().parse::<u32>() {
-            Ok(n) if (0..=100).contains(&n) => {
-                info!("Recording score of {}", n);
-                Some(n)
-            }
-            _ => {
-                debug!("Not recording score.");
-                None
-            }
-        };
+        io::stdin().read_line(&mut input)?;
+        self.score = parse_score(&input).unwrap_or(None);
+        match self.score {
+            Some(n) => info!("Recording score of {}", n),
+            None => debug!("Not recording score."),
+        }
 
         print!("Any notes? Press enter to end (or blank to not save anything): ");
-        io::stdout().flush().unwrap();
+        io::stdout().flush()?;
         input.clear();
-        io::stdin().read_line(&mut input).unwrap();
+        io::stdin().read_line(&mut input)?;
         self.notes = match input.trim() {
             "" => None,
             s => Some(s.to_string()),
         };
+        Ok(())
+    }
+
+    /// Record results given up front rather than asked for at the prompts.
+    pub fn apply_results(&mut self, results: Results) -> anyhow::Result<()> {
+        if let Some(n) = results.score
+            && n > 100
+        {
+            anyhow::bail!("score {} is out of range, it must be from 0 to 100", n);
+        }
+        self.hit = results.hit;
+        self.score = results.score;
+        self.notes = results.notes;
+        self.transcript = results.transcript;
+        Ok(())
+    }
+}
+
+/// Session results passed as flags or a JSON/YAML document, for scripts driving a session without
+/// a TTY.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Results {
+    pub hit: Option<bool>,
+    pub score: Option<u32>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub transcript: Option<String>,
+}
+
+impl Results {
+    /// Parse a results document. JSON is valid YAML, so either works.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(text).context("invalid results document")
+    }
+}
+
+/// Parse a hit answer: y/yes/true, n/no/false, or blank for not recorded.
+pub fn parse_hit(s: &str) -> anyhow::Result<Option<bool>> {
+    match s.trim().to_lowercase().as_str() {
+        "y" | "yes" | "true" => Ok(Some(true)),
+        "n" | "no" | "false" => Ok(Some(false)),
+        "" => Ok(None),
+        other => anyhow::bail!("'{}' isn't y, n or blank", other),
+    }
+}
+
+/// Parse a score from 0 to 100, or blank for not recorded.
+pub fn parse_score(s: &str) -> anyhow::Result<Option<u32>> {
+    match s.trim() {
+        "" => Ok(None),
+        s => match s.parse::<u32>() {
+            Ok(n) if n <= 100 => Ok(Some(n)),
+            _ => anyhow::bail!("'{}' isn't a score from 0 to 100", s),
+        },
+    }
+}
+
+#[cfg(test)]
+impl CompletedTarget {
+    /// An entry for the target hashed from `seed` at `path`, with no results yet.
+    pub fn for_test(seed: &[u8], path: &str) -> Self {
+        Self {
+            rvuid: Rvuid::from_bytes(seed),
+            path: path.to_string(),
+            meta_path: None,
+            hit: None,
+            score: None,
+            notes: None,
+            transcript: None,
+            sketch: None,
+            completed_at: None,
+            reopened_at: None,
+            session: None,
+            group: None,
+        }
     }
 }
 
@@ -290,6 +747,12 @@ impl From<Target> for CompletedTarget {
             hit: None,
             score: None,
             notes: None,
+            transcript: None,
+            sketch: None,
+            completed_at: Some(Utc::now()),
+            reopened_at: None,
+            session: None,
+            group: None,
         }
     }
 }
@@ -303,3 +766,85 @@ impl fmt::Display for CompletedTarget {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_type_from_magic() {
+        assert_eq!(
+            TargetType::from_magic(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some(TargetType::Jpeg)
+        );
+        assert_eq!(
+            TargetType::from_magic(b"\x89PNG\r\n\x1a\n\x00\x00"),
+            Some(TargetType::Png)
+        );
+        assert_eq!(TargetType::from_magic(b"GIF89a..."), Some(TargetType::Gif));
+        assert_eq!(
+            TargetType::from_magic(b"II*\x00...."),
+            Some(TargetType::Tiff)
+        );
+        assert_eq!(
+            TargetType::from_magic(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+            Some(TargetType::Webp)
+        );
+        assert_eq!(
+            TargetType::from_magic(b"RIFF\x00\x00\x00\x00WAVEfmt "),
+            Some(TargetType::Audio)
+        );
+        assert_eq!(
+            TargetType::from_magic(b"\x00\x00\x00\x18ftypmp42"),
+            Some(TargetType::Video)
+        );
+        assert_eq!(TargetType::from_magic(b"\x00\x00\x00\x18ftypavif"), None);
+        assert_eq!(TargetType::from_magic(b"<!DOCTYPE html>"), None);
+    }
+
+    #[test]
+    fn test_target_type_detect() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00";
+        // Signature wins over a misleading extension.
+        assert_eq!(
+            TargetType::detect(Path::new("foo.jpg"), png),
+            Some(TargetType::Png)
+        );
+        assert_eq!(
+            TargetType::detect(Path::new("foo"), png),
+            Some(TargetType::Png)
+        );
+        // Text and SVG fall back to the extension.
+        assert_eq!(
+            TargetType::detect(Path::new("foo.target"), b"the eiffel tower"),
+            Some(TargetType::Text)
+        );
+        assert_eq!(
+            TargetType::detect(Path::new("foo.svg"), b"<svg></svg>"),
+            Some(TargetType::Svg)
+        );
+        // An HTML error page saved as a JPEG isn't a target.
+        assert_eq!(
+            TargetType::detect(Path::new("foo.jpg"), b"<html>404</html>"),
+            None
+        );
+        assert_eq!(TargetType::detect(Path::new("foo.jpg.yaml"), png), None);
+    }
+
+    #[test]
+    fn test_results_parse() {
+        let json = Results::parse(r#"{"hit": true, "score": 70, "notes": "tall"}"#).unwrap();
+        assert_eq!(json.hit, Some(true));
+        assert_eq!(json.score, Some(70));
+        assert_eq!(json.notes.as_deref(), Some("tall"));
+        let yaml = Results::parse("hit: false\nscore: 5\n").unwrap();
+        assert_eq!(yaml.hit, Some(false));
+        assert_eq!(yaml.notes, None);
+        assert!(Results::parse("").unwrap().hit.is_none());
+        assert!(Results::parse("hitt: true").is_err());
+        assert_eq!(parse_hit(" Yes\n").unwrap(), Some(true));
+        assert!(parse_hit("maybe").is_err());
+        assert_eq!(parse_score("100").unwrap(), Some(100));
+        assert!(parse_score("101").is_err());
+    }
+}
@@ -0,0 +1,492 @@
+use chrono::{DateTime, Utc};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use log::warn;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::bundle::ComponentPaths;
+use crate::preview::{self, BlockCells};
+use crate::rich::RichTarget;
+use crate::target::{CompletedTarget, Target, TargetType, parse_hit, parse_score};
+
+const TICK: Duration = Duration::from_millis(250);
+const FORM_FIELDS: [&str; 3] = ["Hit (y/n)", "Score (0-100)", "Notes"];
+
+#[derive(Debug, PartialEq)]
+enum Phase {
+    Viewing,
+    Confirm,
+    Revealed,
+}
+
+/// A full-screen session: timer and notes while viewing, a confirmation before the reveal, then
+/// the target next to a scoring form (or on its own when not recording).
+struct Session<'a> {
+    target: &'a Target,
+    skip_open: bool,
+    record: bool,
+    phase: Phase,
+    started: Instant,
+    elapsed: Option<Duration>,
+    revealed_at: Option<DateTime<Utc>>,
+    frontload: usize,
+    transcript: String,
+    fields: [String; 3],
+    focus: usize,
+    error: Option<String>,
+    image: Option<(Rect, BlockCells)>,
+    /// Worked out once at the reveal rather than on every frame, since bundles are extracted.
+    components: ComponentPaths,
+    lines: Vec<String>,
+    preview: Option<(PathBuf, TargetType)>,
+    done: Option<Option<CompletedTarget>>,
+}
+
+/// How a session in the terminal ended.
+pub struct Outcome {
+    /// When the target was revealed, if the viewer got that far.
+    pub revealed_at: Option<DateTime<Utc>>,
+    /// The results to record, or `None` if the viewer quit without recording anything. Without
+    /// `record` there's no scoring form, and it's always `None`.
+    pub completed: Option<CompletedTarget>,
+}
+
+/// Run a session for `target` in the terminal.
+pub fn run(
+    target: &Target,
+    frontload: u8,
+    skip_open: bool,
+    record: bool,
+) -> anyhow::Result<Outcome> {
+    let mut session = Session {
+        target,
+        skip_open,
+        record,
+        phase: Phase::Viewing,
+        started: Instant::now(),
+        elapsed: None,
+        revealed_at: None,
+        frontload: target.frontloading.len().min(frontload as usize),
+        transcript: String::new(),
+        fields: Default::default(),
+        focus: 0,
+        error: None,
+        image: None,
+        components: ComponentPaths::default(),
+        lines: Vec::new(),
+        preview: None,
+        done: None,
+    };
+    let mut terminal = ratatui::init();
+    let result = session.run(&mut terminal);
+    ratatui::restore();
+    Ok(Outcome {
+        revealed_at: session.revealed_at,
+        completed: result?,
+    })
+}
+
+impl Session<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<Option<CompletedTarget>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.on_key(key);
+            }
+            if let Some(done) = self.done.take() {
+                return Ok(done);
+            }
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && key.code == KeyCode::Char('c') {
+            self.done = Some(None);
+            return;
+        }
+        match self.phase {
+            Phase::Viewing => match key.code {
+                KeyCode::Char('f') if ctrl => {
+                    self.frontload = (self.frontload + 1).min(self.target.frontloading.len());
+                }
+                KeyCode::Char('r') if ctrl => self.phase = Phase::Confirm,
+                KeyCode::Char(c) if !ctrl => self.transcript.push(c),
+                KeyCode::Enter => self.transcript.push('\n'),
+                KeyCode::Backspace => {
+                    self.transcript.pop();
+                }
+                _ => {}
+            },
+            Phase::Confirm => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.reveal(),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    self.phase = Phase::Viewing
+                }
+                _ => {}
+            },
+            Phase::Revealed if !self.record => {
+                if matches!(key.code, KeyCode::Enter | KeyCode::Esc) {
+                    self.done = Some(None);
+                }
+            }
+            Phase::Revealed => match key.code {
+                KeyCode::Tab | KeyCode::Down => self.focus = (self.focus + 1) % FORM_FIELDS.len(),
+                KeyCode::BackTab | KeyCode::Up => {
+                    self.focus = (self.focus + FORM_FIELDS.len() - 1) % FORM_FIELDS.len()
+                }
+                KeyCode::Enter => self.submit(),
+                KeyCode::Esc => self.done = Some(None),
+                KeyCode::Char(c) if !ctrl => self.fields[self.focus].push(c),
+                KeyCode::Backspace => {
+                    self.fields[self.focus].pop();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn reveal(&mut self) {
+        self.phase = Phase::Revealed;
+        self.elapsed = Some(self.started.elapsed());
+        self.revealed_at = Some(Utc::now());
+        if let Some(ref bundle) = self.target.bundle {
+            self.components = bundle.component_paths().unwrap_or_else(|e| {
+                warn!("Failed to extract bundle: {}", e);
+                ComponentPaths::default()
+            });
+        }
+        self.lines = reveal_lines(self.target, &self.components.paths);
+        self.preview = preview_source(self.target, &self.components.paths);
+        if self.skip_open || !preview::gui_available() {
+            return;
+        }
+        for path in external_files(self.target, &self.components.paths) {
+            if let Err(e) = open::that(&path) {
+                warn!("Failed to open {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Validate the scoring form, leaving an error on screen if something's off.
+    fn submit(&mut self) {
+        let hit = match parse_hit(&self.fields[0]) {
+            Ok(hit) => hit,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                self.focus = 0;
+                return;
+            }
+        };
+        let score = match parse_score(&self.fields[1]) {
+            Ok(score) => score,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                self.focus = 1;
+                return;
+            }
+        };
+        let mut completed_target = CompletedTarget::from(self.target.clone());
+        completed_target.hit = hit;
+        completed_target.score = score;
+        completed_target.notes = non_empty(&self.fields[2]);
+        completed_target.transcript = non_empty(&self.transcript);
+        self.done = Some(Some(completed_target));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        self.draw_header(frame, header);
+        match self.phase {
+            Phase::Viewing | Phase::Confirm => self.draw_notes(frame, body),
+            Phase::Revealed if !self.record => self.draw_target(frame, body),
+            Phase::Revealed => {
+                let [target_area, form_area] =
+                    Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                        .areas(body);
+                self.draw_target(frame, target_area);
+                self.draw_form(frame, form_area);
+            }
+        }
+        let help = match self.phase {
+            Phase::Viewing => "Ctrl+F frontloading | Ctrl+R reveal | Ctrl+C quit without recording",
+            Phase::Confirm => "y reveal | n keep viewing",
+            Phase::Revealed if !self.record => "Enter or Esc quit",
+            Phase::Revealed => "Tab next field | Enter save | Esc quit without recording",
+        };
+        frame.render_widget(
+            Paragraph::new(help).style(Style::default().fg(Color::DarkGray)),
+            footer,
+        );
+        if self.phase == Phase::Confirm {
+            self.draw_confirm(frame);
+        }
+    }
+
+    fn draw_header(&self, frame: &mut Frame, area: Rect) {
+        let elapsed = self.elapsed.unwrap_or_else(|| self.started.elapsed());
+        let mut lines = vec![Line::from(vec![
+            Span::raw("Target: "),
+            Span::styled(
+                self.target.rvuid.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(format!(
+                "    Elapsed: {:02}:{:02}",
+                elapsed.as_secs() / 60,
+                elapsed.as_secs() % 60
+            )),
+        ])];
+        if self.frontload > 0 {
+            lines.push(Line::from(format!(
+                "Frontloading: {:?}",
+                &self.target.frontloading[..self.frontload]
+            )));
+        }
+        let block = Block::default().borders(Borders::ALL).title(" psitool ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_notes(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Remote viewer, begin viewing. Notes ");
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<&str> = self.transcript.split('\n').collect();
+        let scroll = lines.len().saturating_sub(height) as u16;
+        let text = format!("{}_", self.transcript);
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(block)
+                .wrap(Wrap { trim: false })
+                .scroll((scroll, 0)),
+            area,
+        );
+    }
+
+    fn draw_confirm(&self, frame: &mut Frame) {
+        let [area] = Layout::horizontal([Constraint::Length(50)])
+            .flex(Flex::Center)
+            .areas(frame.area());
+        let [area] = Layout::vertical([Constraint::Length(5)])
+            .flex(Flex::Center)
+            .areas(area);
+        frame.render_widget(Clear, area);
+        let block = Block::default().borders(Borders::ALL).title(" Reveal ");
+        frame.render_widget(
+            Paragraph::new("Are you done viewing?\nReveal the target now? (y/n)")
+                .block(block)
+                .wrap(Wrap { trim: true }),
+            area,
+        );
+    }
+
+    fn draw_target(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title(" Target ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let lines: Vec<Line> = self.lines.iter().map(|l| Line::from(l.as_str())).collect();
+        let Some((ref path, ref target_type)) = self.preview else {
+            frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), inner);
+            return;
+        };
+        let text_height = (lines.len() as u16 + 1).min(inner.height / 2);
+        let [text_area, image_area] =
+            Layout::vertical([Constraint::Length(text_height), Constraint::Min(1)]).areas(inner);
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), text_area);
+        if self.image.as_ref().map(|(r, _)| *r) != Some(image_area) {
+            match preview::block_cells(
+                path,
+                target_type,
+                image_area.width as u32,
+                image_area.height as u32,
+            ) {
+                Ok(cells) => self.image = Some((image_area, cells)),
+                Err(e) => {
+                    warn!("Failed to preview {}: {}", path.display(), e);
+                    self.image = Some((image_area, Vec::new()));
+                }
+            }
+        }
+        if let Some((_, ref cells)) = self.image {
+            let rows: Vec<Line> = cells
+                .iter()
+                .map(|row| {
+                    Line::from(
+                        row.iter()
+                            .map(|([tr, tg, tb], [br, bg, bb])| {
+                                Span::styled(
+                                    "\u{2580}",
+                                    Style::default()
+                                        .fg(Color::Rgb(*tr, *tg, *tb))
+                                        .bg(Color::Rgb(*br, *bg, *bb)),
+                                )
+                            })
+                            .collect::<Vec<Span>>(),
+                    )
+                })
+                .collect();
+            frame.render_widget(Paragraph::new(rows), image_area);
+        }
+    }
+
+    fn draw_form(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title(" Results ");
+        let mut lines = Vec::new();
+        for (i, label) in FORM_FIELDS.iter().enumerate() {
+            let style = if i == self.focus {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            let cursor = if i == self.focus { "_" } else { "" };
+            lines.push(Line::from(Span::styled(*label, style)));
+            lines.push(Line::from(format!("  {}{}", self.fields[i], cursor)));
+            lines.push(Line::from(""));
+        }
+        if let Some(ref error) = self.error {
+            lines.push(Line::from(Span::styled(
+                error.clone(),
+                Style::default().fg(Color::Red),
+            )));
+        }
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+/// Text shown about the target after the reveal, given where its bundle components are on disk.
+fn reveal_lines(target: &Target, components: &[PathBuf]) -> Vec<String> {
+    let mut lines = vec![format!("Path: {}", target.path.display())];
+    match target.target_type {
+        TargetType::Text => {
+            lines.push("Target Text:".to_string());
+            let text = fs::read_to_string(&target.path).unwrap_or_default();
+            lines.extend(text.lines().map(String::from));
+        }
+        TargetType::Markdown => {
+            if let Some(ref rich) = target.rich {
+                lines.extend(rich_lines(rich));
+            }
+        }
+        TargetType::Bundle => {
+            if let Some(ref bundle) = target.bundle {
+                if let Some(ref title) = bundle.title {
+                    lines.push(format!("Bundle: {}", title));
+                }
+                for (i, (component, path)) in bundle.components.iter().zip(components).enumerate() {
+                    lines.push(format!(
+                        "[{}/{}] {}",
+                        i + 1,
+                        bundle.components.len(),
+                        component.name
+                    ));
+                    if let Some(ref caption) = component.caption {
+                        lines.push(format!("Caption: {}", caption));
+                    }
+                    let text = fs::read_to_string(path).unwrap_or_default();
+                    match component.target_type {
+                        TargetType::Text => lines.extend(text.lines().map(String::from)),
+                        TargetType::Markdown => {
+                            if let Ok(rich) = RichTarget::parse(&text) {
+                                lines.extend(rich_lines(&rich));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    if let Some(ref meta_path) = target.meta_path {
+        lines.push(format!("YAML meta: {}", meta_path.display()));
+    }
+    if !target.meta.is_empty() {
+        for (key, val) in target.iter_meta() {
+            lines.push(format!("{}: {}", key, val));
+        }
+    }
+    lines
+}
+
+fn rich_lines(rich: &RichTarget) -> Vec<String> {
+    let mut lines: Vec<String> = rich
+        .iter_fields()
+        .into_iter()
+        .map(|(key, val)| format!("{}: {}", key, val))
+        .collect();
+    lines.extend(rich.body.lines().map(String::from));
+    lines
+}
+
+/// The image drawn next to the scoring form, if there is one.
+fn preview_source(target: &Target, components: &[PathBuf]) -> Option<(PathBuf, TargetType)> {
+    if target.target_type.is_image() {
+        return Some((target.path.clone(), target.target_type.clone()));
+    }
+    if let Some(ref rich) = target.rich {
+        let feedback = rich.feedback_path(&target.path)?;
+        let target_type = TargetType::parse(&feedback).filter(|t| t.is_image())?;
+        return feedback.exists().then_some((feedback, target_type));
+    }
+    let bundle = target.bundle.as_ref()?;
+    bundle
+        .components
+        .iter()
+        .zip(components)
+        .find(|(c, _)| c.target_type.is_image())
+        .map(|(c, p)| (p.clone(), c.target_type.clone()))
+}
+
+/// Files to open in their desktop applications on reveal, anything that isn't shown as text.
+fn external_files(target: &Target, components: &[PathBuf]) -> Vec<PathBuf> {
+    match target.target_type {
+        TargetType::Text => Vec::new(),
+        TargetType::Markdown => target
+            .rich
+            .as_ref()
+            .and_then(|r| r.feedback_path(&target.path))
+            .filter(|p| p.exists())
+            .into_iter()
+            .collect(),
+        TargetType::Bundle => target
+            .bundle
+            .as_ref()
+            .map(|b| {
+                b.components
+                    .iter()
+                    .zip(components)
+                    .filter(|(c, _)| {
+                        !matches!(c.target_type, TargetType::Text | TargetType::Markdown)
+                    })
+                    .map(|(_, p)| p.clone())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => vec![target.path.clone()],
+    }
+}
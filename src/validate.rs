@@ -0,0 +1,407 @@
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bundle::Bundle;
+use crate::rich::RichTarget;
+use crate::target::{TargetType, YamlData};
+
+/// Subfolder of a pool where `psi-pool check --quarantine` moves invalid targets.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Check that the bytes really are the given target type, and that they're not obviously
+/// truncated. This doesn't fully decode anything, it just checks the signature and the few
+/// structural markers each format has (trailers, chunk sizes, header offsets).
+pub fn validate_bytes(bytes: &[u8], target_type: &TargetType) -> anyhow::Result<()> {
+    if bytes.is_empty() {
+        anyhow::bail!("file is empty");
+    }
+    if looks_like_html(bytes) {
+        anyhow::bail!("file is an HTML page, not a {:?}", target_type);
+    }
+    match target_type {
+        TargetType::Text => {
+            let text = std::str::from_utf8(bytes).context("text target isn't valid UTF-8")?;
+            if text.trim().is_empty() {
+                anyhow::bail!("text target is blank");
+            }
+        }
+        TargetType::Markdown => {
+            let text = std::str::from_utf8(bytes).context("markdown target isn't valid UTF-8")?;
+            let rich = RichTarget::parse(text)?;
+            if rich.body.is_empty() && rich.title.is_none() && rich.feedback.is_none() {
+                anyhow::bail!("markdown target is blank");
+            }
+        }
+        TargetType::Bundle => {
+            Bundle::from_archive(Path::new("bundle"), bytes)?;
+        }
+        TargetType::Svg => {
+            let text = std::str::from_utf8(bytes).context("SVG isn't valid UTF-8")?;
+            if !text.contains("<svg") {
+                anyhow::bail!("SVG has no <svg> element");
+            }
+        }
+        _ => {
+            let magic = TargetType::from_magic(bytes);
+            if magic.as_ref() != Some(target_type) {
+                anyhow::bail!(
+                    "signature doesn't match, expected {:?} but found {:?}",
+                    target_type,
+                    magic
+                );
+            }
+            check_structure(bytes, target_type)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a target (and its YAML sidecar, if any) into the pool's quarantine folder.
+/// Returns the new path of the target.
+pub fn quarantine(path: &Path) -> anyhow::Result<PathBuf> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let qdir = parent.join(QUARANTINE_DIR);
+    fs::create_dir_all(&qdir)?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("no file name in {}", path.display()))?;
+    let dest = unused_path(&qdir, &file_name.to_string_lossy());
+    fs::rename(path, &dest)
+        .with_context(|| format!("failed to move {} to {}", path.display(), dest.display()))?;
+    let sidecar = path.with_file_name(format!("{}.yaml", file_name.to_string_lossy()));
+    if sidecar.exists() {
+        fs::rename(&sidecar, YamlData::sidecar_path(&dest))?;
+    }
+    Ok(dest)
+}
+
+/// A path for `name` in `dir` that doesn't exist yet, adding `-1`, `-2`... before the extension
+/// if it's taken, so an earlier quarantined file with the same name is kept.
+fn unused_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    let mut dest = dir.join(name);
+    let mut n = 1;
+    while dest.exists() || YamlData::sidecar_path(&dest).exists() {
+        dest = dir.join(format!("{}-{}{}", stem, n, ext));
+        n += 1;
+    }
+    dest
+}
+
+fn looks_like_html(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(512)];
+    let head = String::from_utf8_lossy(head)
+        .trim_start()
+        .to_ascii_lowercase();
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+/// Strip the zero padding some servers and tools leave at the end of a file.
+fn trim_padding(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &bytes[..end]
+}
+
+fn riff_size_ok(bytes: &[u8]) -> bool {
+    if bytes.len() < 8 {
+        return false;
+    }
+    let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    bytes.len() >= size.saturating_add(8)
+}
+
+fn check_structure(bytes: &[u8], target_type: &TargetType) -> anyhow::Result<()> {
+    match target_type {
+        TargetType::Jpeg => {
+            if !trim_padding(bytes).ends_with(&[0xFF, 0xD9]) {
+                anyhow::bail!("JPEG is truncated (no end-of-image marker)");
+            }
+        }
+        TargetType::Png => {
+            if bytes.len() < 33 || &bytes[12..16] != b"IHDR" {
+                anyhow::bail!("PNG has no IHDR header");
+            }
+            let trimmed = trim_padding(bytes);
+            if trimmed.len() < 12 || &trimmed[trimmed.len() - 8..trimmed.len() - 4] != b"IEND" {
+                anyhow::bail!("PNG is truncated (no IEND chunk)");
+            }
+        }
+        TargetType::Gif => {
+            if !trim_padding(bytes).ends_with(&[0x3B]) {
+                anyhow::bail!("GIF is truncated (no trailer)");
+            }
+        }
+        TargetType::Tiff => {
+            if bytes.len() < 8 {
+                anyhow::bail!("TIFF header is truncated");
+            }
+            let ifd = if bytes.starts_with(b"II") {
+                u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+            } else {
+                u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+            } as usize;
+            if ifd < 8 || ifd >= bytes.len() {
+                anyhow::bail!(
+                    "TIFF is truncated (first IFD at {} is outside the file)",
+                    ifd
+                );
+            }
+        }
+        TargetType::Webp => {
+            if !riff_size_ok(bytes) {
+                anyhow::bail!("WebP is truncated (RIFF size is larger than the file)");
+            }
+        }
+        TargetType::Video | TargetType::Audio => {
+            if bytes.starts_with(b"RIFF") {
+                if !riff_size_ok(bytes) {
+                    anyhow::bail!(
+                        "{:?} is truncated (RIFF size is larger than the file)",
+                        target_type
+                    );
+                }
+            } else if bytes.starts_with(b"ID3") {
+                check_id3(bytes)?;
+            } else if bytes.starts_with(b"OggS") {
+                check_ogg(bytes)?;
+            } else if bytes.starts_with(b"fLaC") {
+                check_flac(bytes)?;
+            } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+                check_ebml(bytes)?;
+            } else if bytes.get(4..8) == Some(b"ftyp") {
+                check_mp4(bytes)?;
+            } else {
+                check_mpeg_frame(bytes, 0)?;
+            }
+        }
+        TargetType::Text | TargetType::Markdown | TargetType::Svg | TargetType::Bundle => {}
+    }
+    Ok(())
+}
+
+/// An ID3v2 tag, then the first MPEG audio frame right after it.
+fn check_id3(bytes: &[u8]) -> anyhow::Result<()> {
+    if bytes.len() < 10 || bytes[6..10].iter().any(|&b| b >= 0x80) {
+        anyhow::bail!("MP3 is truncated (incomplete ID3 header)");
+    }
+    // The tag size is "syncsafe": 7 bits a byte.
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |size, &b| (size << 7) | b as usize);
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    let mut offset = 10 + size + footer;
+    // Some taggers pad past the size they write.
+    while bytes.get(offset) == Some(&0) {
+        offset += 1;
+    }
+    check_mpeg_frame(bytes, offset)
+}
+
+/// A valid MPEG audio frame header at `offset`.
+fn check_mpeg_frame(bytes: &[u8], offset: usize) -> anyhow::Result<()> {
+    let Some(header) = bytes.get(offset..offset + 4) else {
+        anyhow::bail!("MP3 is truncated (no audio frame)");
+    };
+    let version = (header[1] >> 3) & 0b11;
+    let layer = (header[1] >> 1) & 0b11;
+    let bitrate = header[2] >> 4;
+    let sample_rate = (header[2] >> 2) & 0b11;
+    if header[0] != 0xFF
+        || header[1] & 0xE0 != 0xE0
+        || version == 0b01
+        || layer == 0b00
+        || bitrate == 0b1111
+        || sample_rate == 0b11
+    {
+        anyhow::bail!("MP3 has no valid audio frame at byte {}", offset);
+    }
+    Ok(())
+}
+
+/// A complete first Ogg page: the 27 byte header, its segment table and the segments.
+fn check_ogg(bytes: &[u8]) -> anyhow::Result<()> {
+    if bytes.len() < 27 || bytes[4] != 0 {
+        anyhow::bail!("Ogg is truncated (incomplete page header)");
+    }
+    let segments = bytes[26] as usize;
+    let Some(table) = bytes.get(27..27 + segments) else {
+        anyhow::bail!("Ogg is truncated (incomplete segment table)");
+    };
+    let page_len = 27 + segments + table.iter().map(|&s| s as usize).sum::<usize>();
+    if bytes.len() < page_len {
+        anyhow::bail!("Ogg is truncated (first page is incomplete)");
+    }
+    Ok(())
+}
+
+/// The STREAMINFO metadata block every FLAC file starts with.
+fn check_flac(bytes: &[u8]) -> anyhow::Result<()> {
+    let Some(header) = bytes.get(4..8) else {
+        anyhow::bail!("FLAC is truncated (no STREAMINFO block)");
+    };
+    let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+    if header[0] & 0x7F != 0 || len != 34 {
+        anyhow::bail!("FLAC doesn't start with a STREAMINFO block");
+    }
+    if bytes.len() < 8 + len {
+        anyhow::bail!("FLAC is truncated (incomplete STREAMINFO block)");
+    }
+    Ok(())
+}
+
+/// An EBML variable-length integer at `offset`, with its length in bytes. `None` for the value
+/// when it's the reserved "unknown size".
+fn ebml_vint(bytes: &[u8], offset: usize) -> Option<(Option<u64>, usize)> {
+    let first = *bytes.get(offset)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let rest = bytes.get(offset + 1..offset + len)?;
+    let mask = if len == 8 { 0 } else { 0xFFu8 >> len };
+    let value = rest
+        .iter()
+        .fold((first & mask) as u64, |v, &b| (v << 8) | b as u64);
+    let unknown = value == (1u64 << (7 * len)) - 1;
+    Some(((!unknown).then_some(value), len))
+}
+
+/// A complete EBML header followed by a Matroska/WebM Segment.
+fn check_ebml(bytes: &[u8]) -> anyhow::Result<()> {
+    let Some((Some(size), len)) = ebml_vint(bytes, 4) else {
+        anyhow::bail!("Matroska is truncated (incomplete EBML header)");
+    };
+    let segment = (4 + len).saturating_add(size as usize);
+    if bytes.get(segment..segment.saturating_add(4)) != Some(&[0x18, 0x53, 0x80, 0x67]) {
+        anyhow::bail!("Matroska is truncated (no Segment after the EBML header)");
+    }
+    Ok(())
+}
+
+/// The `ftyp` box, then whole top-level boxes including a `moov` or `mdat`. A download that's cut
+/// off leaves its last box shorter than it says it is.
+fn check_mp4(bytes: &[u8]) -> anyhow::Result<()> {
+    let mut offset = 0usize;
+    let mut found = false;
+    while offset + 8 <= bytes.len() {
+        let size = u32::from_be_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]) as u64;
+        let kind = &bytes[offset + 4..offset + 8];
+        let size = match size {
+            0 => (bytes.len() - offset) as u64,
+            1 => match bytes.get(offset + 8..offset + 16) {
+                Some(large) => u64::from_be_bytes(large.try_into().unwrap()),
+                None => anyhow::bail!("MP4 is truncated (incomplete box header)"),
+            },
+            size => size,
+        };
+        if size < 8 || size > (bytes.len() - offset) as u64 {
+            anyhow::bail!(
+                "MP4 is truncated ('{}' box is larger than the file)",
+                String::from_utf8_lossy(kind)
+            );
+        }
+        found |= kind == b"moov" || kind == b"mdat";
+        offset += size as usize;
+    }
+    if !found {
+        anyhow::bail!("MP4 has no 'moov' or 'mdat' box");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x00\x01\x00\x00\x00\x01\x08\x02\x00\x00\x00\x90wS\xde\x00\x00\x00\x00IEND\xaeB`\x82";
+
+    #[test]
+    fn test_validate_png() {
+        assert!(validate_bytes(PNG, &TargetType::Png).is_ok());
+        assert!(validate_bytes(&PNG[..PNG.len() - 10], &TargetType::Png).is_err());
+        assert!(validate_bytes(PNG, &TargetType::Jpeg).is_err());
+    }
+
+    #[test]
+    fn test_validate_jpeg() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0xFF, 0xD9, 0x00, 0x00];
+        assert!(validate_bytes(&jpeg, &TargetType::Jpeg).is_ok());
+        assert!(validate_bytes(&jpeg[..6], &TargetType::Jpeg).is_err());
+    }
+
+    #[test]
+    fn test_validate_html_and_text() {
+        let page = b"  <!DOCTYPE html><html><body>Too many requests</body></html>";
+        assert!(validate_bytes(page, &TargetType::Jpeg).is_err());
+        assert!(validate_bytes(page, &TargetType::Text).is_err());
+        assert!(validate_bytes(b"The Mona Lisa", &TargetType::Text).is_ok());
+        assert!(validate_bytes(b"  \n", &TargetType::Text).is_err());
+        assert!(validate_bytes(b"<svg></svg>", &TargetType::Svg).is_ok());
+    }
+
+    #[test]
+    fn test_validate_short_audio() {
+        assert!(validate_bytes(b"ID3", &TargetType::Audio).is_err());
+        assert!(validate_bytes(&[0xFF, 0xFB], &TargetType::Audio).is_err());
+    }
+
+    #[test]
+    fn test_validate_media_structure() {
+        let frame = [0xFF, 0xFB, 0x90, 0x64, 0x00];
+        let mp3 = [
+            b"ID3\x04\x00\x00\x00\x00\x00\x02\x00\x00".as_slice(),
+            &frame,
+        ]
+        .concat();
+        assert!(validate_bytes(&mp3, &TargetType::Audio).is_ok());
+        assert!(validate_bytes(&mp3[..12], &TargetType::Audio).is_err());
+
+        let mut ogg = b"OggS\x00\x02".to_vec();
+        ogg.extend([0; 20]);
+        ogg.extend([1, 3, b'a', b'b', b'c']);
+        assert!(validate_bytes(&ogg, &TargetType::Audio).is_ok());
+        assert!(validate_bytes(&ogg[..ogg.len() - 1], &TargetType::Audio).is_err());
+
+        let mut flac = b"fLaC\x80\x00\x00\x22".to_vec();
+        flac.extend([0; 34]);
+        assert!(validate_bytes(&flac, &TargetType::Audio).is_ok());
+        assert!(validate_bytes(&flac[..20], &TargetType::Audio).is_err());
+
+        let mkv = [
+            0x1A, 0x45, 0xDF, 0xA3, 0x81, 0x00, 0x18, 0x53, 0x80, 0x67, 0x01,
+        ];
+        assert!(validate_bytes(&mkv, &TargetType::Video).is_ok());
+        assert!(validate_bytes(&mkv[..8], &TargetType::Video).is_err());
+
+        let mut mp4 = b"\x00\x00\x00\x10ftypisom\x00\x00\x02\x00".to_vec();
+        mp4.extend(b"\x00\x00\x00\x0cmdat\x01\x02\x03\x04");
+        assert!(validate_bytes(&mp4, &TargetType::Video).is_ok());
+        assert!(validate_bytes(&mp4[..mp4.len() - 1], &TargetType::Video).is_err());
+        assert!(validate_bytes(&mp4[..16], &TargetType::Video).is_err());
+    }
+
+    #[test]
+    fn test_quarantine_keeps_earlier_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("broken.jpg");
+        fs::write(&path, b"first").unwrap();
+        fs::write(YamlData::sidecar_path(&path), b"query: first").unwrap();
+        let first = quarantine(&path).unwrap();
+        fs::write(&path, b"second").unwrap();
+        let second = quarantine(&path).unwrap();
+        assert_eq!(second, dir.join(QUARANTINE_DIR).join("broken-1.jpg"));
+        assert_eq!(fs::read(&first).unwrap(), b"first");
+        assert_eq!(fs::read(&second).unwrap(), b"second");
+        assert!(YamlData::sidecar_path(&first).exists());
+    }
+}
@@ -0,0 +1,796 @@
+use anyhow::Context;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::download::{self, RateLimiter, RetryPolicy};
+use crate::target::Rendition;
+
+pub const API: &str = "https://commons.wikimedia.org/w/api.php";
+
+pub const DEFAULT_QUERY_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 180;
+
+/// The most search results the API hands out per request.
+const MAX_BATCH: usize = 500;
+
+/// The most titles the API takes in one request.
+const MAX_TITLES: usize = 50;
+
+/// How wide a thumbnail to ask for when the original might not fit the filters and there's no
+/// `max_width` or `thumb_width` to go by.
+const DEFAULT_THUMB_WIDTH: u32 = 2048;
+
+/// Ask the API to turn us away, rather than add to the load, when its database replicas are lagging
+/// more than this many seconds.
+const MAXLAG: &str = "5";
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    error: Option<ApiError>,
+    query: Option<Query>,
+    /// Pass these back to get the next batch. Missing once there's nothing more.
+    #[serde(rename = "continue")]
+    cont: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: String,
+    info: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    #[serde(default)]
+    pages: HashMap<String, Page>,
+    #[serde(default)]
+    categorymembers: Vec<Member>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    title: String,
+}
+
+/// Where a query's files come from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// A full-text search of File: pages.
+    Search(String),
+    /// The files in a category, and in its subcategories down to `depth` levels.
+    Category { name: String, depth: u32 },
+    /// These files exactly.
+    Titles(Vec<String>),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Search(query) => write!(f, "{}", query),
+            Source::Category { name, depth: 0 } => write!(f, "{}", category_title(name)),
+            Source::Category { name, depth } => {
+                write!(f, "{} (depth {})", category_title(name), depth)
+            }
+            Source::Titles(titles) => write!(f, "{} titles", titles.len()),
+        }
+    }
+}
+
+/// Limits on which files a query downloads. Anything left out isn't checked.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filters {
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_bytes: Option<u64>,
+    /// Like `image/jpeg`, or `image/*` for any image.
+    pub mime_types: Option<Vec<String>>,
+    /// Width divided by height, so 1.0 is square and above it is landscape.
+    pub min_aspect: Option<f64>,
+    pub max_aspect: Option<f64>,
+    /// How wide a thumbnail to download instead, when the original is too big. Defaults to
+    /// `max_width`.
+    pub thumb_width: Option<u32>,
+}
+
+impl Filters {
+    /// These filters, with any set in `other` taking their place.
+    pub fn merged(&self, other: &Filters) -> Filters {
+        Filters {
+            min_width: other.min_width.or(self.min_width),
+            max_width: other.max_width.or(self.max_width),
+            min_height: other.min_height.or(self.min_height),
+            max_height: other.max_height.or(self.max_height),
+            max_bytes: other.max_bytes.or(self.max_bytes),
+            mime_types: other.mime_types.clone().or(self.mime_types.clone()),
+            min_aspect: other.min_aspect.or(self.min_aspect),
+            max_aspect: other.max_aspect.or(self.max_aspect),
+            thumb_width: other.thumb_width.or(self.thumb_width),
+        }
+    }
+
+    /// The `iiurlwidth` and `iiurlheight` to ask for, if a thumbnail might be needed.
+    pub fn thumb_size(&self) -> (Option<u32>, Option<u32>) {
+        let may_need_thumb = self.max_width.is_some()
+            || self.max_height.is_some()
+            || self.max_bytes.is_some()
+            || self.mime_types.is_some();
+        if !may_need_thumb {
+            return (None, None);
+        }
+        let width = self
+            .thumb_width
+            .or(self.max_width)
+            .unwrap_or(DEFAULT_THUMB_WIDTH);
+        (Some(width), self.max_height)
+    }
+
+    fn allows_mime(&self, mime: Option<&str>) -> bool {
+        let Some(ref allowed) = self.mime_types else {
+            return true;
+        };
+        let Some(mime) = mime else {
+            return false;
+        };
+        allowed.iter().any(|a| match a.strip_suffix("/*") {
+            Some(kind) => mime.split('/').next() == Some(kind),
+            None => a.eq_ignore_ascii_case(mime),
+        })
+    }
+
+    /// Why a file of this type and size can't be saved as is, if it can't.
+    fn too_big(
+        &self,
+        mime: Option<&str>,
+        width: Option<u32>,
+        height: Option<u32>,
+        bytes: Option<u64>,
+    ) -> Option<String> {
+        if !self.allows_mime(mime) {
+            return Some(format!(
+                "{} isn't an allowed type",
+                mime.unwrap_or("unknown")
+            ));
+        }
+        let over = |value: Option<u64>, max: Option<u64>, what: &str| match (value, max) {
+            (Some(v), Some(max)) if v > max => Some(format!("{} {} is over {}", what, v, max)),
+            (None, Some(_)) => Some(format!("{} is unknown", what)),
+            _ => None,
+        };
+        over(width.map(u64::from), self.max_width.map(u64::from), "width")
+            .or_else(|| {
+                over(
+                    height.map(u64::from),
+                    self.max_height.map(u64::from),
+                    "height",
+                )
+            })
+            // An unknown size is checked once it's downloaded.
+            .or_else(|| bytes.and_then(|b| over(Some(b), self.max_bytes, "size")))
+    }
+
+    /// Pick what to download for a file: the original if it passes, or else the thumbnail, or the
+    /// reason neither will do.
+    pub fn choose(&self, info: &ImageInfo) -> Result<Rendition, String> {
+        let under = |value: Option<u32>, min: Option<u32>, what: &str| match (value, min) {
+            (Some(v), Some(min)) if v < min => Err(format!("{} {} is under {}", what, v, min)),
+            (None, Some(_)) => Err(format!("{} is unknown", what)),
+            _ => Ok(()),
+        };
+        under(info.width, self.min_width, "width")?;
+        under(info.height, self.min_height, "height")?;
+        if self.min_aspect.is_some() || self.max_aspect.is_some() {
+            let (Some(w), Some(h)) = (info.width, info.height) else {
+                return Err("aspect ratio is unknown".to_string());
+            };
+            let aspect = w as f64 / h.max(1) as f64;
+            if self.min_aspect.is_some_and(|min| aspect < min)
+                || self.max_aspect.is_some_and(|max| aspect > max)
+            {
+                return Err(format!("aspect ratio {:.2} is out of range", aspect));
+            }
+        }
+        let original = Rendition {
+            thumbnail: false,
+            url: info.url.clone(),
+            width: info.width,
+            height: info.height,
+            mime: info.mime.clone(),
+            bytes: info.size,
+            original_url: info.url.clone(),
+            original_width: info.width,
+            original_height: info.height,
+            original_mime: info.mime.clone(),
+            original_bytes: info.size,
+        };
+        let Some(reason) = self.too_big(info.mime.as_deref(), info.width, info.height, info.size)
+        else {
+            return Ok(original);
+        };
+        let Some(ref thumburl) = info.thumburl else {
+            return Err(reason);
+        };
+        let thumb_mime = info.thumbmime.as_deref().or(info.mime.as_deref());
+        if thumburl == &info.url
+            || self
+                .too_big(thumb_mime, info.thumbwidth, info.thumbheight, None)
+                .is_some()
+        {
+            return Err(reason);
+        }
+        Ok(Rendition {
+            thumbnail: true,
+            url: thumburl.clone(),
+            width: info.thumbwidth,
+            height: info.thumbheight,
+            mime: thumb_mime.map(String::from),
+            bytes: None,
+            ..original
+        })
+    }
+}
+
+/// The pages gathered so far, without duplicates, in the order they were first seen.
+#[derive(Default)]
+struct Pages {
+    pages: Vec<Page>,
+    by_title: HashMap<String, usize>,
+    /// The `iiurlwidth` and `iiurlheight` to ask for with them.
+    thumb: (Option<u32>, Option<u32>),
+}
+
+impl Pages {
+    /// Add a page, or fill in the imageinfo it was missing if it's already there. Returns whether
+    /// it was new.
+    fn add(&mut self, page: Page) -> bool {
+        match self.by_title.get(&page.title) {
+            Some(&idx) => {
+                if self.pages[idx].imageinfo.is_none() {
+                    self.pages[idx].imageinfo = page.imageinfo;
+                }
+                false
+            }
+            None => {
+                self.by_title.insert(page.title.clone(), self.pages.len());
+                self.pages.push(page);
+                true
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    pub title: String,
+    /// Where the page ranked in the search.
+    pub index: Option<u64>,
+    pub imageinfo: Option<Vec<ImageInfo>>,
+}
+
+impl fmt::Display for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Page({})", self.title)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImageInfo {
+    pub url: String,
+    pub extmetadata: Option<HashMap<String, ExtValue>>,
+    pub size: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime: Option<String>,
+    /// A rendition scaled down to fit `iiurlwidth`/`iiurlheight`, when they were asked for.
+    pub thumburl: Option<String>,
+    pub thumbwidth: Option<u32>,
+    pub thumbheight: Option<u32>,
+    pub thumbmime: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExtValue {
+    pub value: serde_json::Value,
+}
+
+fn make_user_agent() -> String {
+    format!(
+        "{}/psi-wm-downloader/{} ({})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_AUTHORS")
+    )
+}
+
+pub fn make_client(timeout_secs: Option<u64>) -> anyhow::Result<reqwest::blocking::Client> {
+    let user_agent = make_user_agent();
+    debug!("using user agent: {}", user_agent);
+    let timeout_secs = timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(user_agent)
+        .timeout(Duration::from_secs(timeout_secs))
+        .build()?;
+    Ok(client)
+}
+
+/// Talks to a MediaWiki API, Wikimedia Commons unless told otherwise.
+pub struct WikiClient {
+    client: reqwest::blocking::Client,
+    api: String,
+    limiter: Arc<RateLimiter>,
+    policy: RetryPolicy,
+    language: Option<String>,
+}
+
+impl WikiClient {
+    /// A client whose requests share `limiter` with whatever else is talking to the same wiki.
+    pub fn new(api: &str, limiter: Arc<RateLimiter>, policy: RetryPolicy) -> anyhow::Result<Self> {
+        reqwest::Url::parse(api).with_context(|| format!("invalid wiki endpoint '{}'", api))?;
+        Ok(Self {
+            client: make_client(Some(DEFAULT_QUERY_TIMEOUT_SECS))?,
+            api: api.to_string(),
+            limiter,
+            policy,
+            language: None,
+        })
+    }
+
+    /// Ask for metadata in this language, where the wiki has it in several.
+    pub fn with_language(mut self, lang: &str) -> Self {
+        self.language = Some(lang.to_string());
+        self
+    }
+
+    /// One API request, retried while the API says it's lagging (`maxlag`) or overloaded.
+    fn get(&self, params: &[(&str, &str)]) -> anyhow::Result<ApiResponse> {
+        let mut attempt = 0;
+        loop {
+            let resp = download::send(&self.limiter, &self.policy, || {
+                self.client
+                    .get(&self.api)
+                    .query(params)
+                    .query(&[("maxlag", MAXLAG)])
+            })?;
+            if !resp.status().is_success() {
+                anyhow::bail!("API error: {}", resp.status());
+            }
+            let asked = download::retry_after(&resp);
+            let resp: ApiResponse = resp.json()?;
+            match resp.error {
+                Some(ref e) if e.code == "maxlag" && attempt < self.policy.retries => {
+                    warn!(
+                        "the API is lagging ({}), waiting",
+                        e.info.as_deref().unwrap_or("no details")
+                    );
+                    download::back_off(&self.limiter, &self.policy, attempt, asked);
+                    attempt += 1;
+                }
+                Some(e) => anyhow::bail!("API error {}: {}", e.code, e.info.unwrap_or_default()),
+                None => return Ok(resp),
+            }
+        }
+    }
+
+    /// The File: pages for a query, up to `limit` of them, in the order the wiki lists them, with
+    /// thumbnails for any the `filters` might find too big.
+    pub fn images(
+        &self,
+        source: &Source,
+        limit: usize,
+        filters: &Filters,
+    ) -> anyhow::Result<Vec<Page>> {
+        let mut pages = Pages {
+            thumb: filters.thumb_size(),
+            ..Default::default()
+        };
+        match source {
+            Source::Search(query) => self.search_images(query, limit, &mut pages)?,
+            Source::Category { name, depth } => {
+                self.category_images(name, *depth, limit, &mut pages)?
+            }
+            Source::Titles(titles) => self.title_images(titles, limit, &mut pages)?,
+        }
+        let mut pages = pages.pages;
+        pages.truncate(limit);
+        // Some installations hand out file URLs relative to the wiki (`//host/...` or `/images/...`).
+        let base = reqwest::Url::parse(&self.api)?;
+        for info in pages
+            .iter_mut()
+            .filter_map(|p| p.imageinfo.as_mut())
+            .flatten()
+        {
+            if let Ok(url) = base.join(&info.url) {
+                info.url = url.to_string();
+            }
+            if let Some(thumburl) = info.thumburl.as_mut()
+                && let Ok(url) = base.join(thumburl)
+            {
+                *thumburl = url.to_string();
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Full-text search of File: pages.
+    fn search_images(&self, query: &str, limit: usize, pages: &mut Pages) -> anyhow::Result<()> {
+        debug!("searching for query {} with limit {}", query, limit);
+        let params = [
+            ("generator", "search"),
+            ("gsrsearch", query),
+            ("gsrnamespace", "6"), // only File: pages
+        ];
+        self.generate(&params, Some("gsrlimit"), limit, pages)
+    }
+
+    /// The files in a category, then in its subcategories down to `depth` levels, breadth first.
+    fn category_images(
+        &self,
+        category: &str,
+        depth: u32,
+        limit: usize,
+        pages: &mut Pages,
+    ) -> anyhow::Result<()> {
+        let mut queue: VecDeque<(String, u32)> = VecDeque::from([(category_title(category), 0)]);
+        let mut visited: HashSet<String> = HashSet::new();
+        while let Some((category, level)) = queue.pop_front() {
+            if pages.pages.len() >= limit {
+                break;
+            }
+            if !visited.insert(category.clone()) {
+                continue;
+            }
+            debug!("listing files in {} with limit {}", category, limit);
+            let params = [
+                ("generator", "categorymembers"),
+                ("gcmtitle", category.as_str()),
+                ("gcmtype", "file"),
+            ];
+            self.generate(&params, Some("gcmlimit"), limit, pages)?;
+            if level < depth {
+                for sub in self.subcategories(&category)? {
+                    queue.push_back((sub, level + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn subcategories(&self, category: &str) -> anyhow::Result<Vec<String>> {
+        let mut found = Vec::new();
+        let mut cont: Vec<(String, String)> = Vec::new();
+        loop {
+            let mut params: Vec<(&str, &str)> = vec![
+                ("action", "query"),
+                ("list", "categorymembers"),
+                ("cmtitle", category),
+                ("cmtype", "subcat"),
+                ("cmlimit", "max"),
+                ("format", "json"),
+            ];
+            params.extend(cont.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            let resp = self.get(&params)?;
+            if let Some(query) = resp.query {
+                found.extend(query.categorymembers.into_iter().map(|m| m.title));
+            }
+            match resp.cont.map(continuation) {
+                Some(next) if next != cont => cont = next,
+                _ => break,
+            }
+        }
+        Ok(found)
+    }
+
+    /// An explicit list of files.
+    fn title_images(
+        &self,
+        titles: &[String],
+        limit: usize,
+        pages: &mut Pages,
+    ) -> anyhow::Result<()> {
+        let titles: Vec<String> = titles.iter().take(limit).map(|t| file_title(t)).collect();
+        for chunk in titles.chunks(MAX_TITLES) {
+            let joined = chunk.join("|");
+            self.generate(&[("titles", joined.as_str())], None, limit, pages)?;
+        }
+        // Ask for them in the order they were listed in, not the order the wiki sent them back.
+        let order: HashMap<&String, usize> =
+            titles.iter().enumerate().map(|(i, t)| (t, i)).collect();
+        pages
+            .pages
+            .sort_by_key(|p| order.get(&p.title).copied().unwrap_or(usize::MAX));
+        Ok(())
+    }
+
+    /// Run a pages query with `prop=imageinfo`, following the API's continuation until there are
+    /// `limit` distinct pages or no more results.
+    fn generate(
+        &self,
+        params: &[(&str, &str)],
+        limit_param: Option<&str>,
+        limit: usize,
+        pages: &mut Pages,
+    ) -> anyhow::Result<()> {
+        let mut cont: Vec<(String, String)> = Vec::new();
+        let mut seen_conts: HashSet<Vec<(String, String)>> = HashSet::new();
+        let thumb_width = pages.thumb.0.map(|w| w.to_string());
+        let thumb_height = pages.thumb.1.map(|h| h.to_string());
+        while pages.pages.len() < limit {
+            let batch = (limit - pages.pages.len()).min(MAX_BATCH).to_string();
+            let mut all: Vec<(&str, &str)> = vec![
+                ("action", "query"),
+                ("prop", "imageinfo"),
+                ("iiprop", "url|extmetadata|size|mime|thumbmime"),
+                ("format", "json"),
+            ];
+            all.extend_from_slice(params);
+            if let Some(ref width) = thumb_width {
+                all.push(("iiurlwidth", width));
+            }
+            if let Some(ref height) = thumb_height {
+                all.push(("iiurlheight", height));
+            }
+            if let Some(ref lang) = self.language {
+                all.push(("iiextmetadatalanguage", lang));
+            }
+            if let Some(limit_param) = limit_param {
+                all.push((limit_param, &batch));
+            }
+            all.extend(cont.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            let resp = self.get(&all)?;
+            let mut batch_pages: Vec<Page> = resp
+                .query
+                .map(|q| q.pages.into_values().collect())
+                .unwrap_or_default();
+            batch_pages.sort_by(|a, b| {
+                (a.index.unwrap_or(u64::MAX), &a.title)
+                    .cmp(&(b.index.unwrap_or(u64::MAX), &b.title))
+            });
+            let mut added = 0;
+            for page in batch_pages {
+                if pages.add(page) {
+                    added += 1;
+                }
+            }
+            debug!("got {} new pages ({} so far)", added, pages.pages.len());
+            let Some(next) = resp.cont else {
+                break;
+            };
+            cont = continuation(next);
+            if !seen_conts.insert(cont.clone()) {
+                warn!("the API sent the same continuation twice, stopping");
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The parameters to send back for the next batch, sorted so they compare equal.
+fn continuation(cont: HashMap<String, serde_json::Value>) -> Vec<(String, String)> {
+    let mut cont: Vec<(String, String)> = cont
+        .into_iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => (k, s),
+            other => (k, other.to_string()),
+        })
+        .collect();
+    cont.sort();
+    cont
+}
+
+/// A category's page title the way the API writes it, like `Category:Lighthouses in Maine`.
+fn category_title(name: &str) -> String {
+    let name = name.trim().replace('_', " ");
+    if name.starts_with("Category:") {
+        name
+    } else {
+        format!("Category:{}", name)
+    }
+}
+
+/// A file's page title the way the API writes it, like `File:Some tree.jpg`.
+fn file_title(name: &str) -> String {
+    let name = name.trim().replace('_', " ");
+    if name.starts_with("File:") {
+        name
+    } else {
+        format!("File:{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn page(title: &str, index: u64) -> serde_json::Value {
+        serde_json::json!({
+            "title": title,
+            "index": index,
+            "imageinfo": [{"url": format!("/images/{}", title)}],
+        })
+    }
+
+    /// Answer `n` API requests with `respond(decoded url, request number)`, returning the URLs.
+    fn mock(
+        n: usize,
+        respond: impl Fn(&str, usize) -> serde_json::Value + Send + 'static,
+    ) -> (WikiClient, thread::JoinHandle<Vec<String>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let api = format!("http://{}/w/api.php", server.server_addr());
+        let handle = thread::spawn(move || {
+            let mut urls = Vec::new();
+            for request in server.incoming_requests() {
+                let url = request.url().replace('+', " ");
+                let url = urlencoding::decode(&url).unwrap().into_owned();
+                let body = respond(&url, urls.len());
+                urls.push(url);
+                request
+                    .respond(tiny_http::Response::from_string(body.to_string()))
+                    .unwrap();
+                if urls.len() == n {
+                    break;
+                }
+            }
+            urls
+        });
+        let policy = RetryPolicy {
+            retries: 2,
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+        };
+        let client = WikiClient::new(&api, Arc::new(RateLimiter::new(0.0)), policy).unwrap();
+        (client, handle)
+    }
+
+    fn titles(pages: Vec<Page>) -> Vec<String> {
+        pages.into_iter().map(|p| p.title).collect()
+    }
+
+    #[test]
+    fn test_search_follows_continue() {
+        let (client, handle) = mock(6, |url, n| {
+            if n == 0 {
+                serde_json::json!({"error": {"code": "maxlag", "info": "Waiting for a database server"}})
+            } else if url.contains("gsroffset=4") {
+                serde_json::json!({"query": {"pages": {"5": page("File:E.jpg", 5)}}})
+            } else if url.contains("gsroffset=2") {
+                serde_json::json!({
+                    "continue": {"gsroffset": 4, "continue": "gsroffset||"},
+                    "query": {"pages": {"2": page("File:B.jpg", 3), "4": page("File:D.jpg", 4)}},
+                })
+            } else {
+                serde_json::json!({
+                    "continue": {"gsroffset": 2, "continue": "gsroffset||"},
+                    "query": {"pages": {"2": page("File:B.jpg", 2), "1": page("File:A.jpg", 1)}},
+                })
+            }
+        });
+        let search = Source::Search("lighthouse".to_string());
+        let pages = client.images(&search, 3, &Filters::default()).unwrap();
+        assert_eq!(
+            pages[0].imageinfo.as_ref().unwrap()[0].url,
+            client.api.replace("/w/api.php", "/images/File:A.jpg")
+        );
+        assert_eq!(
+            titles(pages),
+            vec!["File:A.jpg", "File:B.jpg", "File:D.jpg"]
+        );
+        assert_eq!(
+            titles(client.images(&search, 100, &Filters::default()).unwrap()),
+            vec!["File:A.jpg", "File:B.jpg", "File:D.jpg", "File:E.jpg"]
+        );
+        let urls = handle.join().unwrap();
+        assert_eq!(urls.len(), 6);
+        // The first request was turned away for maxlag and sent again.
+        assert!(urls[0].contains("maxlag=5"));
+        assert_eq!(urls[0], urls[1]);
+        assert!(urls[1].contains("gsrlimit=3"));
+        assert!(!urls[1].contains("gsroffset"));
+        assert!(urls[2].contains("gsroffset=2") && urls[2].contains("gsrlimit=1"));
+        assert!(urls[5].contains("gsroffset=4") && urls[5].contains("gsrlimit=97"));
+    }
+
+    #[test]
+    fn test_category_and_titles() {
+        let (client, handle) = mock(4, |url, _| {
+            if url.contains("titles=") {
+                serde_json::json!({"query": {"pages": {
+                    "7": page("File:A.jpg", 0),
+                    "-1": page("File:Some tree.jpg", 0),
+                }}})
+            } else if url.contains("list=categorymembers") {
+                serde_json::json!({"query": {"categorymembers": [{"title": "Category:Lighthouses in Maine"}]}})
+            } else if url.contains("gcmtitle=Category:Lighthouses in Maine") {
+                serde_json::json!({"query": {"pages": {"3": page("File:Maine.jpg", 0)}}})
+            } else {
+                serde_json::json!({"query": {"pages": {"2": page("File:Lighthouse.jpg", 0)}}})
+            }
+        });
+        let category = Source::Category {
+            name: "Lighthouses".to_string(),
+            depth: 1,
+        };
+        assert_eq!(
+            titles(client.images(&category, 10, &Filters::default()).unwrap()),
+            vec!["File:Lighthouse.jpg", "File:Maine.jpg"]
+        );
+        let listed = Source::Titles(vec!["Some_tree.jpg".to_string(), "File:A.jpg".to_string()]);
+        assert_eq!(
+            titles(client.images(&listed, 10, &Filters::default()).unwrap()),
+            vec!["File:Some tree.jpg", "File:A.jpg"]
+        );
+        let urls = handle.join().unwrap();
+        assert!(
+            urls[0].contains("gcmtitle=Category:Lighthouses&") && urls[0].contains("gcmtype=file")
+        );
+        assert!(
+            urls[1].contains("cmtitle=Category:Lighthouses&") && urls[1].contains("cmtype=subcat")
+        );
+        assert!(urls[3].contains("titles=File:Some tree.jpg|File:A.jpg"));
+    }
+
+    #[test]
+    fn test_filters_choose_rendition() {
+        let pool = Filters {
+            min_width: Some(800),
+            max_bytes: Some(5_000_000),
+            mime_types: Some(vec!["image/*".to_string()]),
+            ..Default::default()
+        };
+        let filters = pool.merged(&Filters {
+            max_width: Some(2000),
+            max_aspect: Some(2.0),
+            ..Default::default()
+        });
+        assert_eq!(filters.thumb_size(), (Some(2000), None));
+        assert_eq!(Filters::default().thumb_size(), (None, None));
+
+        let info = ImageInfo {
+            url: "https://upload.example/big.tif".to_string(),
+            width: Some(4000),
+            height: Some(3000),
+            size: Some(40_000_000),
+            mime: Some("image/tiff".to_string()),
+            thumburl: Some("https://upload.example/2000px-big.tif.jpg".to_string()),
+            thumbwidth: Some(2000),
+            thumbheight: Some(1500),
+            thumbmime: Some("image/jpeg".to_string()),
+            ..Default::default()
+        };
+        let rendition = filters.choose(&info).unwrap();
+        assert!(rendition.thumbnail);
+        assert_eq!(rendition.url, "https://upload.example/2000px-big.tif.jpg");
+        assert_eq!(rendition.mime.as_deref(), Some("image/jpeg"));
+        assert_eq!(rendition.original_bytes, Some(40_000_000));
+
+        let small = ImageInfo {
+            width: Some(1000),
+            height: Some(800),
+            size: Some(300_000),
+            thumburl: None,
+            ..info
+        };
+        assert!(!filters.choose(&small).unwrap().thumbnail);
+        let narrow = ImageInfo {
+            width: Some(600),
+            ..small
+        };
+        assert_eq!(
+            filters.choose(&narrow).unwrap_err(),
+            "width 600 is under 800"
+        );
+        let panorama = ImageInfo {
+            width: Some(1800),
+            height: Some(600),
+            ..narrow
+        };
+        assert!(filters.choose(&panorama).unwrap_err().contains("aspect"));
+    }
+}